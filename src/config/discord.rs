@@ -1,11 +1,14 @@
 use serde::{Serialize, Deserialize};
-use async_timer::timer::{SyncTimer, Timer, SyncPlatform, new_sync_timer};
 
 use std::io;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use core::fmt;
 
 use super::FileSystemLoad;
-use crate::constants::CONFIG_UPDATE_INTERVAL;
+use crate::constants::{CONFIG_UPDATE_INTERVAL, ROLL_HISTORY_LEN, ROSTER_LIMIT, DEFAULT_WAIFUS};
+use crate::dice::Naturals;
+use crate::initiative::Tracker;
+use crate::rng::Seeds;
 
 pub const DISCORD_TOKEN: &str = env!("JEANNE_DISCORD_TOKEN");
 
@@ -16,10 +19,343 @@ pub struct Channels {
     pub bisokuzenshin: HashSet<u64>,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+///Notifications, that channel can subscribe to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subscription {
+    Naze,
+    Bisokuzenshin,
+}
+
+impl Subscription {
+    ///Parses string, returning subscription, if matches any
+    pub fn from_str(mut text: &str) -> Option<Self> {
+        text = text.trim();
+
+        if text.eq_ignore_ascii_case("Naze") {
+            Some(Subscription::Naze)
+        } else if text.eq_ignore_ascii_case("Bisokuzenshin") {
+            Some(Subscription::Bisokuzenshin)
+        } else {
+            None
+        }
+    }
+}
+
+impl Channels {
+    ///Subscribes or unsubscribes channel, returning whether channel is subscribed now.
+    pub fn toggle_subscription(&mut self, sub: Subscription, channel: u64) -> bool {
+        let channels = match sub {
+            Subscription::Naze => &mut self.naze,
+            Subscription::Bisokuzenshin => &mut self.bisokuzenshin,
+        };
+
+        match channels.take(&channel).is_some() {
+            true => false,
+            false => channels.insert(channel),
+        }
+    }
+
+    ///Sets or removes welcome channel, returning whether channel is welcome channel now.
+    pub fn toggle_welcome(&mut self, channel: u64) -> bool {
+        match self.welcome == channel {
+            true => {
+                self.welcome = 0;
+                false
+            },
+            false => {
+                self.welcome = channel;
+                true
+            },
+        }
+    }
+}
+
+///Discord configuration
+///
+///New fields must be appended to the end in order to keep loading older configs.
+#[derive(Default, Debug, Serialize)]
 pub struct DiscordConfig {
     pub channels: Channels,
     pub owner: u64,
+    ///Channel to post error reports, owner's DM is used if `0`
+    pub reports: u64,
+    ///Rolls by user and channel id
+    pub rolls: HashMap<(u64, u64), RollHistory>,
+    ///Dice macros by user id
+    pub macros: HashMap<u64, Macros>,
+    ///Dice macros shared within guild by guild id
+    pub guild_macros: HashMap<u64, Macros>,
+    ///Initiative trackers by channel id
+    pub initiative: HashMap<u64, Tracker>,
+    ///Waifu rosters by guild id, `DEFAULT_WAIFUS` are used if guild has none
+    pub waifus: HashMap<u64, Vec<Waifu>>,
+    ///Reaction role menus by message id
+    pub menus: HashMap<u64, RoleMenu>,
+    ///Dice seeds, kept to verify rolls after restart
+    pub seeds: Seeds,
+    ///Guilds, where rolls are shown with ticket to verify them
+    pub tickets: HashSet<u64>,
+}
+
+impl_lenient_deserialize!(DiscordConfig {
+    channels,
+    owner,
+    reports,
+    rolls,
+    macros,
+    guild_macros,
+    initiative,
+    waifus,
+    menus,
+    seeds,
+    tickets
+});
+
+///Dice expressions by lower case name
+pub type Macros = BTreeMap<String, String>;
+
+///Character, that can be selected by `set_waifu`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Waifu {
+    pub name: String,
+    ///Alternative names, in lower case
+    pub aliases: Vec<String>,
+    ///Role id, `0` if not set yet
+    pub role: u64,
+    ///Emoji as written in message, can be empty
+    pub emoji: String,
+    ///RGB colour, `0` for default one
+    pub colour: u32,
+}
+
+impl Waifu {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            aliases: Vec::new(),
+            role: 0,
+            emoji: String::new(),
+            colour: 0,
+        }
+    }
+
+    ///Returns whether waifu is known by `name`.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+///Possible errors when adding to roster
+pub enum RosterError {
+    ///Name or alias is used by another waifu
+    Taken(String),
+    ///Roster has `ROSTER_LIMIT` waifus
+    TooMany,
+}
+
+impl fmt::Display for RosterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RosterError::Taken(name) => write!(f, "Name '{}' is already taken", name),
+            RosterError::TooMany => write!(f, "Too many waifus, no more than {} is allowed", ROSTER_LIMIT),
+        }
+    }
+}
+
+///Role given for reaction to menu
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MenuRole {
+    ///Emoji as written in message
+    pub emoji: String,
+    ///Id of custom emoji or unicode emoji itself, identifying reaction
+    pub key: String,
+    pub role: u64,
+}
+
+///Message, that gives roles for reactions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoleMenu {
+    pub guild: u64,
+    pub channel: u64,
+    ///Whether member can have only one role of menu
+    pub exclusive: bool,
+    pub roles: Vec<MenuRole>,
+}
+
+impl RoleMenu {
+    ///Returns role for reaction.
+    pub fn role(&self, key: &str) -> Option<&MenuRole> {
+        self.roles.iter().find(|role| role.key == key)
+    }
+
+    ///Returns role for reaction with roles, that it replaces.
+    pub fn pick(&self, key: &str) -> Option<(&MenuRole, Vec<&MenuRole>)> {
+        let role = self.role(key)?;
+        let others = match self.exclusive {
+            true => self.roles.iter().filter(|other| other.role != role.role).collect(),
+            false => Vec::new(),
+        };
+
+        Some((role, others))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_ROSTER: Vec<Waifu> = DEFAULT_WAIFUS.iter().map(|name| Waifu::new(name)).collect();
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RollRecord {
+    ///Unix timestamp in seconds
+    pub time: u64,
+    pub expr: String,
+    ///Result of each die
+    pub result: String,
+    pub total: u32,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct RollStats {
+    pub rolls: u64,
+    ///Sum of all totals
+    pub sum: u64,
+    pub d20: u64,
+    pub nat20: u64,
+    pub nat1: u64,
+}
+
+impl fmt::Display for RollStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let average = match self.rolls {
+            0 => 0.0,
+            rolls => self.sum as f64 / rolls as f64,
+        };
+
+        writeln!(f, "Rolls:   {}", self.rolls)?;
+        writeln!(f, "Average: {:.2}", average)?;
+        writeln!(f, "d20:     {}", self.d20)?;
+        writeln!(f, "Nat 20:  {}", self.nat20)?;
+        writeln!(f, "Nat 1:   {}", self.nat1)
+    }
+}
+
+///Rolls of user in channel
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct RollHistory {
+    ///Last `ROLL_HISTORY_LEN` rolls, oldest first
+    pub last: VecDeque<RollRecord>,
+    pub stats: RollStats,
+}
+
+impl RollHistory {
+    pub fn push(&mut self, record: RollRecord, naturals: Naturals) {
+        self.stats.rolls += 1;
+        self.stats.sum += record.total as u64;
+        self.stats.d20 += naturals.d20;
+        self.stats.nat20 += naturals.nat20;
+        self.stats.nat1 += naturals.nat1;
+
+        if self.last.len() >= ROLL_HISTORY_LEN {
+            self.last.pop_front();
+        }
+        self.last.push_back(record);
+    }
+}
+
+impl DiscordConfig {
+    ///Returns user's macro, falling back to macro of guild.
+    pub fn dice_macro(&self, user: u64, guild: Option<u64>, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+
+        match self.macros.get(&user).and_then(|macros| macros.get(&name)) {
+            Some(body) => Some(body.as_str()),
+            None => guild.and_then(|guild| self.guild_macros.get(&guild))
+                         .and_then(|macros| macros.get(&name))
+                         .map(String::as_str),
+        }
+    }
+
+    ///Returns waifu roster of guild.
+    pub fn waifus(&self, guild: u64) -> &[Waifu] {
+        match self.waifus.get(&guild) {
+            Some(roster) => roster.as_slice(),
+            None => DEFAULT_ROSTER.as_slice(),
+        }
+    }
+
+    ///Adds or replaces waifu with the same name.
+    pub fn add_waifu(&mut self, guild: u64, waifu: Waifu) -> Result<(), RosterError> {
+        let roster = self.waifus.entry(guild).or_insert_with(|| DEFAULT_ROSTER.clone());
+        let others = roster.iter().filter(|other| !other.name.eq_ignore_ascii_case(&waifu.name));
+
+        for other in others {
+            if let Some(name) = core::iter::once(&waifu.name).chain(waifu.aliases.iter()).find(|name| other.is_named(name)) {
+                return Err(RosterError::Taken(name.clone()));
+            }
+        }
+
+        match roster.iter_mut().find(|other| other.name.eq_ignore_ascii_case(&waifu.name)) {
+            Some(other) => *other = waifu,
+            None => match roster.len() >= ROSTER_LIMIT {
+                true => return Err(RosterError::TooMany),
+                false => roster.push(waifu),
+            },
+        }
+
+        Ok(())
+    }
+
+    ///Fills unset roles of waifus with roles named `Team<Name>`, that were used before roster could be configured.
+    ///
+    ///Returns number of filled roles, roster is kept as it is if there are none.
+    pub fn fill_waifu_roles(&mut self, guild: u64, roles: &[(u64, &str)]) -> usize {
+        let mut roster = self.waifus(guild).to_vec();
+        let mut num = 0;
+
+        for waifu in roster.iter_mut().filter(|waifu| waifu.role == 0) {
+            let name = format!("Team{}", waifu.name);
+            if let Some((role, _)) = roles.iter().find(|(_, role)| role.eq_ignore_ascii_case(&name)) {
+                waifu.role = *role;
+                num += 1;
+            }
+        }
+
+        if num > 0 {
+            self.waifus.insert(guild, roster);
+        }
+
+        num
+    }
+
+    ///Removes waifu by name or alias, returning her.
+    pub fn remove_waifu(&mut self, guild: u64, name: &str) -> Option<Waifu> {
+        let roster = self.waifus.entry(guild).or_insert_with(|| DEFAULT_ROSTER.clone());
+
+        roster.iter().position(|waifu| waifu.is_named(name)).map(|idx| roster.remove(idx))
+    }
+
+    ///Enables or disables roll tickets in guild, returning whether they are enabled now.
+    pub fn toggle_tickets(&mut self, guild: u64) -> bool {
+        match self.tickets.remove(&guild) {
+            true => false,
+            false => self.tickets.insert(guild),
+        }
+    }
+
+    ///Sets or removes reports channel, returning whether channel is reports channel now.
+    pub fn toggle_reports(&mut self, channel: u64) -> bool {
+        match self.reports == channel {
+            true => {
+                self.reports = 0;
+                false
+            },
+            false => {
+                self.reports = channel;
+                true
+            },
+        }
+    }
 }
 
 impl FileSystemLoad for DiscordConfig {
@@ -27,20 +363,15 @@ impl FileSystemLoad for DiscordConfig {
 }
 
 pub struct Discord {
-    inner: parking_lot::RwLock<(DiscordConfig, SyncPlatform)>,
+    inner: parking_lot::RwLock<DiscordConfig>,
+    updated: tokio::sync::Notify,
 }
 
 impl Discord {
     pub fn new() -> io::Result<Self> {
-        DiscordConfig::load().map(|config| {
-            let mut timer = new_sync_timer(CONFIG_UPDATE_INTERVAL);
-
-            timer.init(|state| state.register(on_config_update as fn()));
-            timer.cancel();
-
-            Self {
-                inner: parking_lot::RwLock::new((config, timer)),
-            }
+        DiscordConfig::load().map(|config| Self {
+            inner: parking_lot::RwLock::new(config),
+            updated: tokio::sync::Notify::new(),
         })
     }
 
@@ -48,40 +379,264 @@ impl Discord {
         self.with_read(|config| config.save())
     }
 
+    ///Saves config right away, without waiting for `run`.
+    pub fn flush(&self) {
+        on_config_update(self)
+    }
+
     #[inline]
     pub fn with_read<R, F: FnOnce(&DiscordConfig) -> R>(&self, cb: F) -> R {
         let inner = self.inner.read();
 
-        cb(&inner.0)
+        cb(&inner)
     }
 
     #[inline]
     pub fn with_write<R, F: FnOnce(&mut DiscordConfig) -> R>(&self, cb: F) -> R {
-        let mut inner = self.inner.write();
+        let res = cb(&mut self.inner.write());
 
-        let res = cb(&mut inner.0);
+        //Permit is kept until saving task takes it
+        self.updated.notify_one();
+        res
+    }
 
-        match inner.1.tick() {
-            false => match inner.1.is_ticking() {
-                true => return res,
-                false => (),
-            },
-            true => (),
+    ///Saves config `CONFIG_UPDATE_INTERVAL` after it is changed.
+    pub async fn run(&self) {
+        loop {
+            self.updated.notified().await;
+            //Let changes accumulate
+            tokio::time::sleep(CONFIG_UPDATE_INTERVAL).await;
+            on_config_update(self);
         }
-
-        inner.1.restart(CONFIG_UPDATE_INTERVAL);
-        res
     }
 }
 
-fn on_config_update() {
-    match crate::config::DISCORD.save() {
+fn on_config_update(config: &Discord) {
+    match config.save() {
         Ok(_) => {
-            rogu::info!("Discord config is updated.");
+            log::info!("Discord config is updated.");
+            crate::health::set_config_saved(true);
         },
         Err(error) => {
-            rogu::error!("Discord unable to save config: {}", error);
+            log::error!("Discord unable to save config: {}", error);
+            crate::health::set_config_saved(false);
             crate::stats::STATS.increment(crate::stats::DiscordBrokenConfigUpdate);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_subscription() {
+        assert_eq!(Subscription::from_str("naze"), Some(Subscription::Naze));
+        assert_eq!(Subscription::from_str(" BisokuZenshin "), Some(Subscription::Bisokuzenshin));
+        assert_eq!(Subscription::from_str("Jeanne"), None);
+        assert_eq!(Subscription::from_str(""), None);
+    }
+
+    #[test]
+    fn should_toggle_subscription() {
+        let mut channels = Channels::default();
+
+        assert!(channels.toggle_subscription(Subscription::Naze, 1));
+        assert!(channels.toggle_subscription(Subscription::Naze, 2));
+        assert!(channels.toggle_subscription(Subscription::Bisokuzenshin, 1));
+        assert!(channels.naze.contains(&1) && channels.naze.contains(&2));
+        assert!(channels.bisokuzenshin.contains(&1));
+
+        assert!(!channels.toggle_subscription(Subscription::Naze, 1));
+        assert!(!channels.naze.contains(&1));
+        assert!(channels.naze.contains(&2));
+        assert!(channels.bisokuzenshin.contains(&1));
+    }
+
+    #[test]
+    fn should_toggle_welcome_and_reports() {
+        let mut config = DiscordConfig::default();
+
+        assert!(config.channels.toggle_welcome(1));
+        assert_eq!(config.channels.welcome, 1);
+        assert!(config.channels.toggle_welcome(2));
+        assert_eq!(config.channels.welcome, 2);
+        assert!(!config.channels.toggle_welcome(2));
+        assert_eq!(config.channels.welcome, 0);
+
+        assert!(config.toggle_reports(3));
+        assert_eq!(config.reports, 3);
+        assert!(!config.toggle_reports(3));
+        assert_eq!(config.reports, 0);
+
+        assert!(config.toggle_tickets(4));
+        assert!(config.tickets.contains(&4));
+        assert!(!config.toggle_tickets(4));
+        assert!(config.tickets.is_empty());
+    }
+
+    #[test]
+    fn should_keep_limited_roll_history() {
+        let mut history = RollHistory::default();
+
+        for idx in 0..ROLL_HISTORY_LEN + 2 {
+            let record = RollRecord {
+                time: idx as u64,
+                expr: "1d20".to_owned(),
+                result: "[20] = 20".to_owned(),
+                total: 20,
+            };
+            history.push(record, Naturals { d20: 1, nat20: 1, nat1: 0 });
+        }
+
+        assert_eq!(history.last.len(), ROLL_HISTORY_LEN);
+        assert_eq!(history.last.front().map(|record| record.time), Some(2));
+        assert_eq!(history.last.back().map(|record| record.time), Some(ROLL_HISTORY_LEN as u64 + 1));
+        assert_eq!(history.stats.rolls, ROLL_HISTORY_LEN as u64 + 2);
+        assert_eq!(history.stats.nat20, ROLL_HISTORY_LEN as u64 + 2);
+        assert!(history.stats.to_string().contains("Average: 20.00"));
+    }
+
+    #[test]
+    fn should_prefer_user_macro_over_guild_one() {
+        let mut config = DiscordConfig::default();
+        config.macros.entry(1).or_default().insert("attack".to_owned(), "1d20+7".to_owned());
+        config.guild_macros.entry(10).or_default().insert("attack".to_owned(), "1d20+5".to_owned());
+        config.guild_macros.entry(10).or_default().insert("fireball".to_owned(), "8d6".to_owned());
+
+        assert_eq!(config.dice_macro(1, Some(10), "Attack"), Some("1d20+7"));
+        assert_eq!(config.dice_macro(2, Some(10), "attack"), Some("1d20+5"));
+        assert_eq!(config.dice_macro(1, Some(10), "fireball"), Some("8d6"));
+        assert_eq!(config.dice_macro(1, None, "fireball"), None);
+        assert_eq!(config.dice_macro(1, Some(11), "fireball"), None);
+    }
+
+    #[test]
+    fn should_fill_waifu_roles_from_team_roles() {
+        let mut config = DiscordConfig::default();
+
+        assert_eq!(config.fill_waifu_roles(1, &[(10, "Moderator")]), 0);
+        assert!(config.waifus.is_empty());
+
+        let roles = [(10, "Moderator"), (11, &*format!("team{}", DEFAULT_WAIFUS[1])), (12, &*format!("Team{}", DEFAULT_WAIFUS[3]))];
+        assert_eq!(config.fill_waifu_roles(1, &roles), 2);
+        let filled = config.waifus(1).iter().map(|waifu| waifu.role).collect::<Vec<_>>();
+        assert_eq!(filled, [0, 11, 0, 12]);
+        assert_eq!(config.waifus(2).iter().filter(|waifu| waifu.role != 0).count(), 0);
+
+        //Roles set by administrator are kept
+        assert_eq!(config.fill_waifu_roles(1, &[(13, &*format!("Team{}", DEFAULT_WAIFUS[1]))]), 0);
+        assert_eq!(config.waifus(1)[1].role, 11);
+    }
+
+    #[test]
+    fn should_manage_waifu_roster() {
+        let mut config = DiscordConfig::default();
+        assert_eq!(config.waifus(1).iter().map(|waifu| waifu.name.as_str()).collect::<Vec<_>>(), DEFAULT_WAIFUS);
+
+        let mut kaguya = Waifu::new("Kaguya");
+        kaguya.aliases.push("shinomiya".to_owned());
+        kaguya.role = 5;
+        config.add_waifu(1, kaguya.clone()).expect("To add waifu");
+        assert_eq!(config.waifus(1).len(), DEFAULT_WAIFUS.len() + 1);
+        assert!(config.waifus(1).iter().any(|waifu| waifu.is_named("Shinomiya")));
+        //Other guilds keep default roster
+        assert_eq!(config.waifus(2).len(), DEFAULT_WAIFUS.len());
+
+        let mut chika = Waifu::new("Chika");
+        chika.aliases.push("Rinne".to_owned());
+        assert_eq!(config.add_waifu(1, chika), Err(RosterError::Taken("Rinne".to_owned())));
+
+        //Same name replaces waifu
+        kaguya.role = 6;
+        config.add_waifu(1, kaguya).expect("To replace waifu");
+        assert_eq!(config.waifus(1).iter().filter(|waifu| waifu.role == 6).count(), 1);
+
+        assert_eq!(config.remove_waifu(1, "shinomiya").map(|waifu| waifu.name), Some("Kaguya".to_owned()));
+        assert_eq!(config.remove_waifu(1, "shinomiya"), None);
+        for name in DEFAULT_WAIFUS.iter() {
+            assert!(config.remove_waifu(1, name).is_some());
+        }
+        //Guild can have empty roster
+        assert!(config.waifus(1).is_empty());
+
+        for idx in DEFAULT_WAIFUS.len()..ROSTER_LIMIT {
+            config.add_waifu(2, Waifu::new(&format!("Waifu{}", idx))).expect("To add waifu");
+        }
+        assert_eq!(config.add_waifu(2, Waifu::new("Extra")), Err(RosterError::TooMany));
+    }
+
+    #[test]
+    fn should_pick_menu_role() {
+        let role = |key: &str, role| MenuRole {
+            emoji: key.to_owned(),
+            key: key.to_owned(),
+            role,
+        };
+        let mut menu = RoleMenu {
+            guild: 1,
+            channel: 2,
+            exclusive: true,
+            roles: vec![role("a", 10), role("b", 11), role("c", 12)],
+        };
+
+        let (picked, others) = menu.pick("b").expect("To pick role");
+        assert_eq!(picked.role, 11);
+        assert_eq!(others.iter().map(|other| other.role).collect::<Vec<_>>(), [10, 12]);
+        assert!(menu.pick("d").is_none());
+
+        menu.exclusive = false;
+        let (picked, others) = menu.pick("c").expect("To pick role");
+        assert_eq!(picked.role, 12);
+        assert!(others.is_empty());
+    }
+
+    #[test]
+    fn should_load_config_without_trailing_fields() {
+        #[derive(Serialize)]
+        struct OldConfig {
+            channels: Channels,
+            owner: u64,
+        }
+
+        let mut channels = Channels {
+            welcome: 5,
+            ..Default::default()
+        };
+        channels.naze.insert(6);
+
+        let old = bincode::serialize(&OldConfig { channels, owner: 7 }).expect("To serialize");
+        let config: DiscordConfig = super::super::deserialize(&old).expect("To deserialize");
+
+        assert_eq!(config.channels.welcome, 5);
+        assert!(config.channels.naze.contains(&6));
+        assert_eq!(config.owner, 7);
+        assert_eq!(config.reports, 0);
+        assert!(config.rolls.is_empty());
+        assert!(config.macros.is_empty());
+        assert!(config.guild_macros.is_empty());
+        assert!(config.initiative.is_empty());
+        assert_eq!(config.waifus(1).len(), DEFAULT_WAIFUS.len());
+        assert!(config.menus.is_empty());
+        assert!(config.seeds.current.is_none());
+        assert!(config.tickets.is_empty());
+    }
+
+    #[test]
+    fn should_reject_config_truncated_within_field() {
+        #[derive(Serialize)]
+        struct OldConfig {
+            channels: Channels,
+            owner: u64,
+        }
+
+        let old = bincode::serialize(&OldConfig { channels: Channels::default(), owner: 7 }).expect("To serialize");
+        assert!(super::super::deserialize::<DiscordConfig>(&old[..old.len() - 3]).is_err());
+
+        let mut config = DiscordConfig::default();
+        config.tickets.insert(1);
+        let full = bincode::serialize(&config).expect("To serialize");
+        assert!(super::super::deserialize::<DiscordConfig>(&full).is_ok());
+        assert!(super::super::deserialize::<DiscordConfig>(&full[..full.len() - 1]).is_err());
+    }
+}
@@ -1,36 +1,116 @@
 use std::path::{Path, PathBuf};
-use std::io::{self};
+use std::io::{self, Read};
 use std::fs;
 use std::env;
+use core::cell::Cell;
 
 use serde::Serialize;
 use serde::de::{DeserializeOwned};
 
+pub const CMD_PREFIX: &str = "~";
+
+///Implements `Deserialize` that uses default value for missing trailing fields.
+///
+///Bincode is not self describing, so this allows to load config saved by older version,
+///as long as new fields are only appended to the end of top level struct.
+///
+///Config is considered old only if it is loaded by `deserialize` and ends exactly before field,
+///any other error is propagated.
+macro_rules! impl_lenient_deserialize {
+    ($name:ident { $($field:ident),+ }) => {
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                        f.write_str(concat!("struct ", stringify!($name)))
+                    }
+
+                    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                        let mut res = $name::default();
+
+                        $(
+                            //Old config ends here
+                            if stringify!($field) != FIELDS[0] && $crate::config::is_input_end() {
+                                return Ok(res);
+                            }
+
+                            match seq.next_element()? {
+                                Some(value) => res.$field = value,
+                                None => return Ok(res),
+                            }
+                        )+
+
+                        Ok(res)
+                    }
+                }
+
+                const FIELDS: &[&str] = &[$(stringify!($field)),+];
+                deserializer.deserialize_struct(stringify!($name), FIELDS, Visitor)
+            }
+        }
+    }
+}
+
 pub mod discord;
 pub use discord::{DISCORD_TOKEN, Discord};
 
-pub const CMD_PREFIX: &str = "~";
-
 lazy_static::lazy_static! {
     pub static ref DISCORD: Discord = match Discord::new() {
         Ok(discord) => discord,
         Err(error) => {
-            rogu::error!("Unable to load discord config: {}", error);
+            log::error!("Unable to load discord config: {}", error);
             unreachable!()
         }
     };
 }
 
 pub fn init() {
-    rogu::set_level(rogu::Level::TRACE);
-
     lazy_static::initialize(&DISCORD);
 }
 
+///Saves discord config, once it is changed.
+pub async fn run() {
+    DISCORD.run().await
+}
+
+thread_local! {
+    ///Number of bytes left to read by `deserialize`, running on this thread.
+    static INPUT_LEFT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+///Returns whether input of `deserialize` is fully read.
+pub fn is_input_end() -> bool {
+    INPUT_LEFT.with(|left| left.get() == Some(0))
+}
+
+///Reader, that keeps `INPUT_LEFT` up to date.
+struct TrackedInput<'a>(&'a [u8]);
+
+impl Read for TrackedInput<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.0.read(buf);
+        INPUT_LEFT.with(|left| left.set(Some(self.0.len())));
+        res
+    }
+}
+
+///Deserializes config out of `bytes`, allowing old config to lack trailing fields.
+pub fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    INPUT_LEFT.with(|left| left.set(Some(bytes.len())));
+    let res = bincode::deserialize_from(TrackedInput(bytes));
+    INPUT_LEFT.with(|left| left.set(None));
+
+    res
+}
+
 #[inline(always)]
 pub fn load_from_file<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
-    let file = fs::File::open(&path).map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{}: {}", path.display(), error)))?;
-    bincode::deserialize_from(file).map_err(|error| io::Error::new(io::ErrorKind::Other, format!("Invalid config: {}", error)))
+    let bytes = fs::read(&path).map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{}: {}", path.display(), error)))?;
+    deserialize(&bytes).map_err(|error| io::Error::new(io::ErrorKind::Other, format!("Invalid config: {}", error)))
 }
 
 #[inline(always)]
@@ -61,9 +141,14 @@ pub trait FileSystemLoad: Serialize + DeserializeOwned + Default {
         }
     }
 
+    ///Loads config, using default one if there is no file yet.
+    ///
+    ///Invalid config is an error, so that it is not overwritten by default one.
     fn load() -> io::Result<Self> {
-        Self::existing_path().and_then(|path| load_from_file(&path))
-                             .or_else(|_| Ok(Self::default()))
+        match Self::existing_path() {
+            Ok(path) => load_from_file(&path),
+            Err(_) => Ok(Self::default()),
+        }
     }
 
     fn save(&self) -> io::Result<()> {
@@ -1,5 +1,5 @@
 use core::time::Duration;
-use core::{num, fmt};
+use core::num;
 
 pub const ADMIN_CHECK_FAIL: &str = "You're unathorized to access the command.";
 
@@ -7,9 +7,34 @@ pub const MSG_SET_WELCOME: &str = "I've set this channel as welcoming one.";
 pub const MSG_REMOVE_WELCOME: &str = "This channel is no longer welcoming one.";
 pub const MSG_REMOVE_SUB: &str = "Removed subscribtion.";
 pub const MSG_ADD_SUB: &str = "Added subscribtion.";
+pub const MSG_SET_REPORTS: &str = "I'll report errors in this channel.";
+pub const MSG_REMOVE_REPORTS: &str = "Errors will be reported to owner directly.";
+pub const MSG_SET_TICKETS: &str = "Rolls in this server are shown with ticket to verify them.";
+pub const MSG_REMOVE_TICKETS: &str = "Rolls in this server are shown without ticket.";
+pub const MSG_NO_ROLLS: &str = "You haven't rolled anything in this channel yet.";
+pub const MSG_NO_INITIATIVE: &str = "There is no initiative in this channel.";
 pub const MSG_UNKNOWN_SUB: &str = "Unknown type of subscribtion, please check command help.";
 
 pub const CONFIG_UPDATE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+///Twitter sends keep-alive every 30 seconds, so silence longer than that means stream is stuck.
+pub const TWITTER_STREAM_TIMEOUT: Duration = Duration::from_secs(90);
+
+///Interval between error reports, limiting its rate.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+///Time during which the same error is not reported again.
+pub const REPORT_DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+///Interval to replace dice seed, revealing previous one.
+pub const RESEED_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+///Number of rolls to remember per user and channel.
+pub const ROLL_HISTORY_LEN: usize = 50;
+///Number of rolls to show in `~rolls`.
+pub const ROLL_HISTORY_DISPLAY: usize = 10;
+
+///Number of dice macros per user or guild.
+pub const MACROS_LIMIT: usize = 25;
+
+pub const HEALTH_ADDR: &str = "127.0.0.1:8090";
 
 pub const JEANNE_GREETING: &str = "諸君、おはようございます";
 
@@ -21,47 +46,10 @@ pub const JEANNE_TALK: [&str; 5] = [
     "まったく、カイはいつも無茶をしてる\nなぜ私を頼ってならない？\n特別扱い？べー別にそんなつもりはない...\n指揮官にとしてそれは普通だ\n笑えない、花琳！",
 ];
 
-#[derive(Clone, Copy)]
-pub enum Waifu {
-    Rinne,
-    Jeanne,
-    Reiren,
-    Hinemarill,
-}
-
-impl Waifu {
-    ///Parses string, returning waifu variant, if matches any
-    pub fn from_str(mut text: &str) -> Option<Self> {
-        text = text.trim();
-
-        if text.eq_ignore_ascii_case("Rinne") {
-            Some(Waifu::Rinne)
-        } else if text.eq_ignore_ascii_case("Jeanne") {
-            Some(Waifu::Jeanne)
-        } else if text.eq_ignore_ascii_case("Reiren") {
-            Some(Waifu::Reiren)
-        } else if text.eq_ignore_ascii_case("Hinemarill") {
-            Some(Waifu::Hinemarill)
-        } else {
-            None
-        }
-    }
-
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Waifu::Rinne => "Rinne",
-            Waifu::Jeanne => "Jeanne",
-            Waifu::Reiren => "Reiren",
-            Waifu::Hinemarill => "Hinemarill",
-        }
-    }
-}
-
-impl fmt::Display for Waifu {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.as_str())
-    }
-}
+///Waifus of guilds without own roster.
+pub const DEFAULT_WAIFUS: [&str; 4] = ["Rinne", "Jeanne", "Reiren", "Hinemarill"];
+///Number of waifus per guild.
+pub const ROSTER_LIMIT: usize = 20;
 
 pub mod emoji {
     pub mod jeanne {
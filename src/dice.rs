@@ -0,0 +1,849 @@
+use cute_dnd_dice::{Roll, Modifier};
+
+use core::fmt;
+use core::iter::Peekable;
+use core::num::NonZeroU16;
+use core::str::Chars;
+
+///Maximum number of individual dice to display per term
+const DISPLAY_LIMIT: usize = 20;
+///Natural result that gets highlighted
+const D20: u16 = 20;
+///Maximum number of dice in single expression
+const DICE_LIMIT: u32 = 1000;
+///Maximum number of extra dice that exploding term can roll
+const EXPLODE_LIMIT: usize = 100;
+///Maximum number of rolls in single command
+const ROLLS_LIMIT: usize = 12;
+///Maximum length of macro's name
+const MACRO_NAME_MAX_LEN: usize = 32;
+
+#[derive(PartialEq, Eq, Debug)]
+///Possible errors when parsing expression
+pub enum ParseError {
+    ///Nothing to roll
+    Empty,
+    ///Expression ends abruptly
+    Incomplete,
+    ///Missing dice faces
+    MissingFaces,
+    ///Invalid number of dices
+    InvalidNum,
+    ///Invalid number of faces
+    InvalidFaces,
+    ///Invalid value of keep, drop or reroll
+    InvalidSelect,
+    ///Invalid constant modifier
+    InvalidExtra,
+    ///Dice with single face cannot explode
+    EndlessExplode,
+    ///Expression has more than `DICE_LIMIT` dice
+    TooManyDice,
+    ///Invalid number of repeats
+    InvalidRepeat,
+    ///More than `ROLLS_LIMIT` rolls
+    TooManyRolls,
+    ///Macro with such name is not defined
+    UnknownMacro(String),
+    ///Unexpected character
+    Unexpected(char),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => f.write_str("Nothing to roll"),
+            ParseError::Incomplete => f.write_str("Roll is incomplete"),
+            ParseError::MissingFaces => f.write_str("Number of dice's faces is missing"),
+            ParseError::InvalidNum => f.write_str("Number of dices is invalid. Should be positive integer"),
+            ParseError::InvalidFaces => f.write_str("Number of faces is invalid. Should be positive integer"),
+            ParseError::InvalidSelect => f.write_str("Number of dice to keep, drop or reroll is invalid. Should be positive integer"),
+            ParseError::InvalidExtra => f.write_str("Modifier is invalid. Should be positive integer"),
+            ParseError::EndlessExplode => f.write_str("Dice with single face cannot explode"),
+            ParseError::TooManyDice => write!(f, "Too many dice, no more than {} is allowed", DICE_LIMIT),
+            ParseError::InvalidRepeat => f.write_str("Number of repeats is invalid. Should be positive integer"),
+            ParseError::TooManyRolls => write!(f, "Too many rolls, no more than {} is allowed", ROLLS_LIMIT),
+            ParseError::UnknownMacro(name) => write!(f, "Unknown macro '{}'", name),
+            ParseError::Unexpected(ch) => write!(f, "Unexpected '{}'", ch),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+///Dice to count
+pub enum Select {
+    All,
+    KeepHighest(u16),
+    KeepLowest(u16),
+    DropHighest(u16),
+    DropLowest(u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Dice {
+    ///Number of dices
+    pub num: u16,
+    ///Number of faces on dice
+    pub faces: NonZeroU16,
+    pub select: Select,
+    ///Whether to roll extra die on maximum face
+    pub explode: bool,
+    ///Dice with this face or lower are re-rolled once
+    pub reroll: Option<u16>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TermKind {
+    Dice(Dice),
+    Const(u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Term {
+    pub is_neg: bool,
+    pub kind: TermKind,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+///Dice expression, i.e. `1d8+2d6+3` or `4d6dl1`
+///
+///Grammar on top of `[num]d<faces>`:
+///
+///- `kh<n>`/`k<n>`, `kl<n>` - keep highest/lowest `n` dice;
+///- `dh<n>`, `dl<n>` - drop highest/lowest `n` dice;
+///- `!` - roll extra die whenever die shows maximum face;
+///- `r<n>` - reroll once dice showing `n` or lower;
+///- `adv`, `dis` - roll d20 with advantage/disadvantage, same as `2d20kh1`/`2d20kl1`.
+///
+///`n` is `1` when omitted.
+pub struct Expr {
+    pub terms: Vec<Term>,
+}
+
+type Input<'a> = Peekable<Chars<'a>>;
+
+fn parse_num(input: &mut Input) -> Option<Result<u16, ()>> {
+    let mut res: Option<u16> = None;
+    let mut is_overflow = false;
+
+    while let Some(digit) = input.peek().and_then(|ch| ch.to_digit(10)) {
+        input.next();
+        match res.unwrap_or(0).checked_mul(10).and_then(|res| res.checked_add(digit as u16)) {
+            Some(value) => res = Some(value),
+            None => is_overflow = true,
+        }
+    }
+
+    match is_overflow {
+        true => Some(Err(())),
+        false => res.map(Ok),
+    }
+}
+
+#[inline]
+fn parse_select(input: &mut Input) -> Result<u16, ParseError> {
+    match parse_num(input) {
+        None => Ok(1),
+        Some(Ok(0)) | Some(Err(_)) => Err(ParseError::InvalidSelect),
+        Some(Ok(num)) => Ok(num),
+    }
+}
+
+fn parse_dice(input: &mut Input, num: u16) -> Result<Dice, ParseError> {
+    let faces = match parse_num(input) {
+        Some(Ok(faces)) => match NonZeroU16::new(faces) {
+            Some(faces) => faces,
+            None => return Err(ParseError::InvalidFaces),
+        },
+        Some(Err(_)) => return Err(ParseError::InvalidFaces),
+        None => return Err(ParseError::MissingFaces),
+    };
+
+    let mut dice = Dice {
+        num,
+        faces,
+        select: Select::All,
+        explode: false,
+        reroll: None,
+    };
+
+    loop {
+        match input.peek() {
+            Some('k') => {
+                input.next();
+                dice.select = match input.peek() {
+                    Some('l') => {
+                        input.next();
+                        Select::KeepLowest(parse_select(input)?)
+                    },
+                    Some('h') => {
+                        input.next();
+                        Select::KeepHighest(parse_select(input)?)
+                    },
+                    _ => Select::KeepHighest(parse_select(input)?),
+                };
+            },
+            Some('d') => {
+                input.next();
+                dice.select = match input.next() {
+                    Some('l') => Select::DropLowest(parse_select(input)?),
+                    Some('h') => Select::DropHighest(parse_select(input)?),
+                    Some(ch) => return Err(ParseError::Unexpected(ch)),
+                    None => return Err(ParseError::Incomplete),
+                };
+            },
+            Some('!') => {
+                input.next();
+                match faces.get() {
+                    1 => return Err(ParseError::EndlessExplode),
+                    _ => dice.explode = true,
+                }
+            },
+            Some('r') => {
+                input.next();
+                dice.reroll = Some(parse_select(input)?);
+            },
+            _ => break Ok(dice),
+        }
+    }
+}
+
+fn parse_keyword(input: &mut Input, keyword: &str) -> Result<(), ParseError> {
+    for expected in keyword.chars() {
+        match input.next() {
+            Some(ch) if ch == expected => (),
+            Some(ch) => return Err(ParseError::Unexpected(ch)),
+            None => return Err(ParseError::Incomplete),
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_term(input: &mut Input) -> Result<TermKind, ParseError> {
+    const ADVANTAGE: Dice = Dice {
+        num: 2,
+        faces: match NonZeroU16::new(D20) {
+            Some(faces) => faces,
+            None => unreachable!(),
+        },
+        select: Select::KeepHighest(1),
+        explode: false,
+        reroll: None,
+    };
+
+    match input.peek() {
+        Some('a') => {
+            parse_keyword(input, "adv")?;
+            Ok(TermKind::Dice(ADVANTAGE))
+        },
+        Some('d') => {
+            input.next();
+            match input.peek() {
+                Some('i') => {
+                    parse_keyword(input, "is")?;
+                    Ok(TermKind::Dice(Dice {
+                        select: Select::KeepLowest(1),
+                        ..ADVANTAGE
+                    }))
+                },
+                _ => parse_dice(input, 1).map(TermKind::Dice),
+            }
+        },
+        Some(_) => match parse_num(input) {
+            Some(num) => match input.peek() {
+                Some('d') => {
+                    input.next();
+                    match num {
+                        Ok(0) | Err(_) => Err(ParseError::InvalidNum),
+                        Ok(num) => parse_dice(input, num).map(TermKind::Dice),
+                    }
+                },
+                _ => match num {
+                    Ok(num) => Ok(TermKind::Const(num)),
+                    Err(_) => Err(ParseError::InvalidExtra),
+                },
+            },
+            None => match input.next() {
+                Some(ch) => Err(ParseError::Unexpected(ch)),
+                None => unreach!(),
+            },
+        },
+        None => Err(ParseError::Incomplete),
+    }
+}
+
+impl Expr {
+    ///Attempts to parse expression, ignoring whitespaces and case.
+    pub fn from_str(text: &str) -> Result<Self, ParseError> {
+        let text = text.chars().filter(|ch| !ch.is_whitespace()).collect::<String>().to_ascii_lowercase();
+        let mut input = text.chars().peekable();
+        let mut terms = Vec::new();
+        let mut dice_num = 0u32;
+
+        if input.peek().is_none() {
+            return Err(ParseError::Empty);
+        }
+
+        loop {
+            let is_neg = match input.peek() {
+                Some('+') => {
+                    input.next();
+                    false
+                },
+                Some('-') => {
+                    input.next();
+                    true
+                },
+                Some(ch) => match terms.is_empty() {
+                    true => false,
+                    false => return Err(ParseError::Unexpected(*ch)),
+                },
+                None => break,
+            };
+
+            let kind = parse_term(&mut input)?;
+            if let TermKind::Dice(dice) = kind {
+                dice_num += dice.num as u32;
+                if dice_num > DICE_LIMIT {
+                    return Err(ParseError::TooManyDice);
+                }
+            }
+
+            terms.push(Term {
+                is_neg,
+                kind,
+            });
+        }
+
+        Ok(Self {
+            terms,
+        })
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}", self.num, self.faces)?;
+
+        match self.select {
+            Select::All => (),
+            Select::KeepHighest(num) => write!(f, "kh{}", num)?,
+            Select::KeepLowest(num) => write!(f, "kl{}", num)?,
+            Select::DropHighest(num) => write!(f, "dh{}", num)?,
+            Select::DropLowest(num) => write!(f, "dl{}", num)?,
+        }
+
+        if let Some(reroll) = self.reroll {
+            write!(f, "r{}", reroll)?;
+        }
+
+        match self.explode {
+            true => f.write_str("!"),
+            false => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, term) in self.terms.iter().enumerate() {
+            match (idx, term.is_neg) {
+                (_, true) => f.write_str("-")?,
+                (0, false) => (),
+                (_, false) => f.write_str("+")?,
+            }
+
+            match term.kind {
+                TermKind::Dice(dice) => write!(f, "{}", dice)?,
+                TermKind::Const(value) => write!(f, "{}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DieState {
+    ///Die is counted
+    Kept,
+    ///Die is not counted due to keep/drop
+    Dropped,
+    ///Die is replaced by re-roll
+    Rerolled,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Die {
+    pub face: u16,
+    pub faces: u16,
+    pub state: DieState,
+    ///Whether die caused extra die to be rolled
+    pub exploded: bool,
+}
+
+impl fmt::Display for Die {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let strike = match self.state {
+            DieState::Kept => "",
+            DieState::Dropped | DieState::Rerolled => "~~",
+        };
+        let bold = match self.faces == D20 && (self.face == 1 || self.face == D20) {
+            true => "**",
+            false => "",
+        };
+        let exploded = match self.exploded {
+            true => "!",
+            false => "",
+        };
+
+        write!(f, "{}{}{}{}{}{}", strike, bold, self.face, exploded, bold, strike)
+    }
+}
+
+///Result of rolling single term
+pub enum TermOutcome {
+    Dice(Vec<Die>),
+    Const(u16),
+}
+
+impl TermOutcome {
+    fn value(&self) -> i64 {
+        match self {
+            TermOutcome::Dice(dice) => dice.iter().filter(|die| die.state == DieState::Kept).map(|die| die.face as i64).sum(),
+            TermOutcome::Const(value) => *value as i64,
+        }
+    }
+}
+
+impl fmt::Display for TermOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TermOutcome::Dice(dice) => {
+                f.write_str("[")?;
+
+                for (idx, die) in dice.iter().take(DISPLAY_LIMIT).enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+
+                    write!(f, "{}", die)?;
+                }
+
+                if dice.len() > DISPLAY_LIMIT {
+                    write!(f, ", …{} more", dice.len() - DISPLAY_LIMIT)?;
+                }
+
+                f.write_str("]")
+            },
+            TermOutcome::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+fn roll_term<F: FnMut(&Roll) -> u16>(dice: &Dice, roll_die: &mut F) -> Vec<Die> {
+    let die = Roll::new(1, dice.faces, Modifier::Plus(0));
+    let faces = dice.faces.get();
+    let mut res = Vec::with_capacity(dice.num as usize);
+    let mut explosions = 0;
+
+    for _ in 0..dice.num {
+        let mut face = roll_die(&die);
+
+        match dice.reroll {
+            Some(reroll) if face <= reroll => {
+                res.push(Die { face, faces, state: DieState::Rerolled, exploded: false });
+                face = roll_die(&die);
+            },
+            _ => (),
+        }
+
+        loop {
+            let exploded = dice.explode && face == faces && explosions < EXPLODE_LIMIT;
+            res.push(Die { face, faces, state: DieState::Kept, exploded });
+
+            match exploded {
+                true => {
+                    explosions += 1;
+                    face = roll_die(&die);
+                },
+                false => break,
+            }
+        }
+    }
+
+    let mut kept = res.iter().enumerate().filter(|(_, die)| die.state == DieState::Kept).map(|(idx, die)| (idx, die.face)).collect::<Vec<_>>();
+    //Highest first
+    kept.sort_by_key(|(_, face)| core::cmp::Reverse(*face));
+
+    let dropped = match dice.select {
+        Select::All => &kept[..0],
+        Select::KeepHighest(num) => &kept[kept.len().min(num as usize)..],
+        Select::KeepLowest(num) => &kept[..kept.len().saturating_sub(num as usize)],
+        Select::DropHighest(num) => &kept[..kept.len().min(num as usize)],
+        Select::DropLowest(num) => &kept[kept.len().saturating_sub(num as usize)..],
+    };
+
+    for (idx, _) in dropped {
+        res[*idx].state = DieState::Dropped;
+    }
+
+    res
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+///Number of d20 dice and its natural results
+pub struct Naturals {
+    pub d20: u64,
+    pub nat20: u64,
+    pub nat1: u64,
+}
+
+///Expression with result of each die
+pub struct Outcome<'a> {
+    pub expr: &'a Expr,
+    pub terms: Vec<TermOutcome>,
+    ///Sum of terms, never below zero
+    pub total: u32,
+}
+
+impl<'a> Outcome<'a> {
+    fn roll_dice<F: FnMut(&Roll) -> u16>(expr: &'a Expr, mut roll_die: F) -> Self {
+        let mut total = 0i64;
+
+        let terms = expr.terms.iter().map(|term| {
+            let outcome = match term.kind {
+                TermKind::Dice(ref dice) => TermOutcome::Dice(roll_term(dice, &mut roll_die)),
+                TermKind::Const(value) => TermOutcome::Const(value),
+            };
+
+            match term.is_neg {
+                true => total -= outcome.value(),
+                false => total += outcome.value(),
+            }
+
+            outcome
+        }).collect();
+
+        Self {
+            expr,
+            terms,
+            total: total.max(0).min(u32::MAX as i64) as u32,
+        }
+    }
+
+    ///Returns counted d20 dice
+    pub fn naturals(&self) -> Naturals {
+        let mut res = Naturals::default();
+
+        for term in self.terms.iter() {
+            if let TermOutcome::Dice(dice) = term {
+                for die in dice.iter().filter(|die| die.faces == D20 && die.state == DieState::Kept) {
+                    res.d20 += 1;
+                    match die.face {
+                        1 => res.nat1 += 1,
+                        D20 => res.nat20 += 1,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    ///Rolls using specified callable to generate random numbers.
+    pub fn roll_with<R: FnMut() -> u64>(expr: &'a Expr, mut random: R) -> Self {
+        Self::roll_dice(expr, |die| die.roll_with(&mut random))
+    }
+}
+
+///Rolls single d20, consuming random numbers the same way as each die of `Nd20` expression.
+pub fn d20_with<R: FnMut() -> u64>(mut random: R) -> u16 {
+    let faces = match NonZeroU16::new(D20) {
+        Some(faces) => faces,
+        None => unreach!(),
+    };
+
+    Roll::new(1, faces, Modifier::Plus(0)).roll_with(&mut random)
+}
+
+///Result of each term with total, without expression
+pub struct Breakdown<'a, 'b>(pub &'a Outcome<'b>);
+
+impl fmt::Display for Breakdown<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, (term, outcome)) in self.0.expr.terms.iter().zip(self.0.terms.iter()).enumerate() {
+            match (idx, term.is_neg) {
+                (0, true) => f.write_str("-")?,
+                (0, false) => (),
+                (_, true) => f.write_str(" - ")?,
+                (_, false) => f.write_str(" + ")?,
+            }
+
+            write!(f, "{}", outcome)?;
+        }
+
+        write!(f, " = {}", self.0.total)
+    }
+}
+
+impl fmt::Display for Outcome<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} → {}", self.expr, Breakdown(self))
+    }
+}
+
+#[derive(Debug)]
+///Set of rolls, i.e. `6x 4d6dl1` or `d20+5, 2d6+3`
+pub struct Rolls {
+    ///Expression with number of times to roll it
+    pub rolls: Vec<(usize, Expr)>,
+}
+
+///Returns whether text can be used as name of macro.
+///
+///Name consists of letters, digits and `_`, starting with letter, and cannot be expression itself.
+pub fn is_macro_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(ch) if ch.is_ascii_alphabetic() => (),
+        _ => return false,
+    }
+
+    name.len() <= MACRO_NAME_MAX_LEN && name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') && Expr::from_str(name).is_err()
+}
+
+impl Rolls {
+    #[cfg(test)]
+    ///Attempts to parse comma separated expressions, each optionally prefixed with `<n>x`
+    pub fn from_str(text: &str) -> Result<Self, ParseError> {
+        Self::from_str_with(text, |_| None)
+    }
+
+    ///Attempts to parse comma separated expressions or macros, each optionally prefixed with `<n>x`
+    ///
+    ///`resolve` returns expression of macro by its name.
+    pub fn from_str_with<F: FnMut(&str) -> Option<String>>(text: &str, mut resolve: F) -> Result<Self, ParseError> {
+        let mut rolls = Vec::new();
+        let mut num: usize = 0;
+
+        for part in text.split(',').map(str::trim) {
+            let (count, expr) = match part.find(|ch: char| !ch.is_ascii_digit()) {
+                Some(idx) if idx > 0 && part[idx..].starts_with(&['x', 'X'][..]) => match part[..idx].parse() {
+                    Ok(0) | Err(_) => return Err(ParseError::InvalidRepeat),
+                    Ok(count) => (count, &part[idx + 1..]),
+                },
+                _ => (1, part),
+            };
+
+            num = match num.checked_add(count) {
+                Some(num) if num <= ROLLS_LIMIT => num,
+                _ => return Err(ParseError::TooManyRolls),
+            };
+
+            let expr = expr.trim();
+            let expr = match is_macro_name(expr) {
+                true => match resolve(expr) {
+                    Some(body) => Expr::from_str(&body)?,
+                    None => return Err(ParseError::UnknownMacro(expr.to_owned())),
+                },
+                false => Expr::from_str(expr)?,
+            };
+
+            rolls.push((count, expr));
+        }
+
+        Ok(Self {
+            rolls,
+        })
+    }
+
+    ///Returns total number of rolls
+    pub fn len(&self) -> usize {
+        self.rolls.iter().map(|(count, _)| *count).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Returns random number that makes die of `faces` to land on `face`
+    fn face(face: u16, faces: u16) -> u64 {
+        ((face as u64 - 1) << 32).div_ceil(faces as u64)
+    }
+
+    fn roll(expr: &str, faces: &[u16]) -> String {
+        let expr = Expr::from_str(expr).expect("To parse roll");
+        let mut dice = expr.terms.iter().filter_map(|term| match term.kind {
+            TermKind::Dice(dice) => Some(dice.faces.get()),
+            TermKind::Const(_) => None,
+        });
+        let mut term_faces = dice.next().unwrap_or(1);
+        let mut faces = faces.iter();
+
+        //Tests use single kind of dice, unless each term is specified explicitly
+        let outcome = Outcome::roll_with(&expr, || match faces.next() {
+            Some(0) => {
+                term_faces = dice.next().expect("To have next term");
+                face(*faces.next().expect("To have enough dice"), term_faces)
+            },
+            Some(die) => face(*die, term_faces),
+            None => panic!("Not enough dice"),
+        });
+
+        assert!(faces.next().is_none(), "Not all dice are rolled");
+        outcome.to_string()
+    }
+
+    #[test]
+    fn should_show_each_die() {
+        assert_eq!(roll("2d20+2", &[14, 3]), "2d20+2 → [14, 3] + 2 = 19");
+        assert_eq!(roll("3d6-2", &[1, 6, 4]), "3d6-2 → [1, 6, 4] - 2 = 9");
+        assert_eq!(roll("d8", &[5]), "1d8 → [5] = 5");
+        assert_eq!(roll("d4-5", &[2]), "1d4-5 → [2] - 5 = 0");
+    }
+
+    #[test]
+    fn should_highlight_natural_d20() {
+        assert_eq!(roll("3d20", &[20, 1, 10]), "3d20 → [**20**, **1**, 10] = 31");
+        assert_eq!(roll("2d6", &[1, 6]), "2d6 → [1, 6] = 7");
+    }
+
+    #[test]
+    fn should_truncate_huge_roll() {
+        let faces = [1; DISPLAY_LIMIT + 5];
+        let expected = format!("25d1 → [{}, …5 more] = 25", ["1"; DISPLAY_LIMIT].join(", "));
+
+        assert_eq!(roll("25d1", &faces), expected);
+    }
+
+    #[test]
+    fn should_keep_and_drop() {
+        assert_eq!(roll("2d20kh1", &[14, 3]), "2d20kh1 → [14, ~~3~~] = 14");
+        assert_eq!(roll("2d20k", &[3, 14]), "2d20kh1 → [~~3~~, 14] = 14");
+        assert_eq!(roll("2d20kl1+1", &[14, 3]), "2d20kl1+1 → [~~14~~, 3] + 1 = 4");
+        assert_eq!(roll("4d6dl1", &[3, 1, 6, 1]), "4d6dl1 → [3, 1, 6, ~~1~~] = 10");
+        assert_eq!(roll("4d6dh2", &[3, 1, 6, 5]), "4d6dh2 → [3, 1, ~~6~~, ~~5~~] = 4");
+        assert_eq!(roll("2d6kh3", &[3, 1]), "2d6kh3 → [3, 1] = 4");
+        assert_eq!(roll("2d6dl3", &[3, 1]), "2d6dl3 → [~~3~~, ~~1~~] = 0");
+    }
+
+    #[test]
+    fn should_roll_advantage_and_disadvantage() {
+        assert_eq!(roll("adv+5", &[20, 7]), "2d20kh1+5 → [**20**, ~~7~~] + 5 = 25");
+        assert_eq!(roll("DIS", &[20, 7]), "2d20kl1 → [~~**20**~~, 7] = 7");
+    }
+
+    #[test]
+    fn should_explode_dice() {
+        assert_eq!(roll("2d6!", &[6, 6, 2, 3]), "2d6! → [6!, 6!, 2, 3] = 17");
+        assert_eq!(roll("3d6!kh2", &[6, 1, 2, 3]), "3d6kh2! → [6!, ~~1~~, ~~2~~, 3] = 9");
+
+        let mut faces = vec![2; EXPLODE_LIMIT + 1];
+        faces.push(1);
+        let outcome = roll("2d2!", &faces);
+        assert!(outcome.ends_with(&format!(" = {}", 2 * (EXPLODE_LIMIT + 1) + 1)), "{}", outcome);
+    }
+
+    #[test]
+    fn should_reroll_once() {
+        assert_eq!(roll("2d6r1", &[1, 1, 4]), "2d6r1 → [~~1~~, 1, 4] = 5");
+        assert_eq!(roll("2d6r2", &[3, 2, 5]), "2d6r2 → [3, ~~2~~, 5] = 8");
+        assert_eq!(roll("2d20r1kh1", &[1, 20, 5]), "2d20kh1r1 → [~~**1**~~, **20**, ~~5~~] = 20");
+    }
+
+    #[test]
+    fn should_sum_multiple_terms() {
+        assert_eq!(roll("1d8 + 2d6 + 3", &[5, 0, 2, 6]), "1d8+2d6+3 → [5] + [2, 6] + 3 = 16");
+        assert_eq!(roll("-1d4+d6", &[3, 0, 1]), "-1d4+1d6 → -[3] + [1] = 0");
+        assert_eq!(roll("10-1d4", &[3]), "10-1d4 → 10 - [3] = 7");
+    }
+
+    #[test]
+    fn should_reject_invalid_expressions() {
+        assert_eq!(Expr::from_str(" ").unwrap_err(), ParseError::Empty);
+        assert_eq!(Expr::from_str("2d").unwrap_err(), ParseError::MissingFaces);
+        assert_eq!(Expr::from_str("0d6").unwrap_err(), ParseError::InvalidNum);
+        assert_eq!(Expr::from_str("2d0").unwrap_err(), ParseError::InvalidFaces);
+        assert_eq!(Expr::from_str("2d99999").unwrap_err(), ParseError::InvalidFaces);
+        assert_eq!(Expr::from_str("2d6kh0").unwrap_err(), ParseError::InvalidSelect);
+        assert_eq!(Expr::from_str("2d6dx").unwrap_err(), ParseError::Unexpected('x'));
+        assert_eq!(Expr::from_str("2d1!").unwrap_err(), ParseError::EndlessExplode);
+        assert_eq!(Expr::from_str("2d6+").unwrap_err(), ParseError::Incomplete);
+        assert_eq!(Expr::from_str("2d6d").unwrap_err(), ParseError::Incomplete);
+        assert_eq!(Expr::from_str("2d6d3").unwrap_err(), ParseError::Unexpected('3'));
+        assert_eq!(Expr::from_str("2d6*3").unwrap_err(), ParseError::Unexpected('*'));
+        assert_eq!(Expr::from_str("ad").unwrap_err(), ParseError::Incomplete);
+        assert_eq!(Expr::from_str("adx").unwrap_err(), ParseError::Unexpected('x'));
+        assert_eq!(Expr::from_str("600d6+600d6").unwrap_err(), ParseError::TooManyDice);
+    }
+
+    #[test]
+    fn should_count_naturals() {
+        let expr = Expr::from_str("2d20kh1+1d20+d6").expect("To parse roll");
+        let mut faces = [1, 20, 20, 1u16].iter().map(|die| face(*die, D20));
+        let outcome = Outcome::roll_with(&expr, || faces.next().expect("To have enough dice"));
+
+        assert_eq!(outcome.naturals(), Naturals { d20: 2, nat20: 2, nat1: 0 });
+    }
+
+    #[test]
+    fn should_parse_multiple_rolls() {
+        let rolls = Rolls::from_str("6x 4d6dl1").expect("To parse rolls");
+        assert_eq!(rolls.len(), 6);
+        assert_eq!(rolls.rolls.len(), 1);
+        assert_eq!(rolls.rolls[0].0, 6);
+        assert_eq!(rolls.rolls[0].1.to_string(), "4d6dl1");
+
+        let rolls = Rolls::from_str("d20+5, 2X2d6+3,adv").expect("To parse rolls");
+        assert_eq!(rolls.len(), 4);
+        let rolls = rolls.rolls.iter().map(|(count, expr)| (*count, expr.to_string())).collect::<Vec<_>>();
+        assert_eq!(rolls, [(1, "1d20+5".to_owned()), (2, "2d6+3".to_owned()), (1, "2d20kh1".to_owned())]);
+
+        assert_eq!(Rolls::from_str("2d6").expect("To parse rolls").len(), 1);
+    }
+
+    #[test]
+    fn should_reject_invalid_rolls() {
+        assert_eq!(Rolls::from_str("0x d20").unwrap_err(), ParseError::InvalidRepeat);
+        assert_eq!(Rolls::from_str("99999999999999999999x d20").unwrap_err(), ParseError::InvalidRepeat);
+        assert_eq!(Rolls::from_str("13x d20").unwrap_err(), ParseError::TooManyRolls);
+        assert_eq!(Rolls::from_str("6x d20, 6x d6, d4").unwrap_err(), ParseError::TooManyRolls);
+        assert_eq!(Rolls::from_str(&format!("d20, {}x d20", usize::MAX)).unwrap_err(), ParseError::TooManyRolls);
+        assert_eq!(Rolls::from_str("d20,").unwrap_err(), ParseError::Empty);
+        assert_eq!(Rolls::from_str("x d20").unwrap_err(), ParseError::Unexpected('x'));
+        assert_eq!(Rolls::from_str("2x").unwrap_err(), ParseError::Empty);
+    }
+
+    #[test]
+    fn should_validate_macro_name() {
+        assert!(is_macro_name("attack"));
+        assert!(is_macro_name("Fire_Bolt2"));
+        assert!(!is_macro_name(""));
+        assert!(!is_macro_name("2attack"));
+        assert!(!is_macro_name("_attack"));
+        assert!(!is_macro_name("fire bolt"));
+        assert!(!is_macro_name("fire-bolt"));
+        assert!(!is_macro_name("d20"));
+        assert!(!is_macro_name("adv"));
+        assert!(!is_macro_name(&"a".repeat(MACRO_NAME_MAX_LEN + 1)));
+    }
+
+    #[test]
+    fn should_expand_macros() {
+        let resolve = |name: &str| match name {
+            "attack" => Some("1d20+7".to_owned()),
+            "broken" => Some("1d".to_owned()),
+            _ => None,
+        };
+
+        let rolls = Rolls::from_str_with("attack, 2x attack, d6", resolve).expect("To parse rolls");
+        let rolls = rolls.rolls.iter().map(|(count, expr)| (*count, expr.to_string())).collect::<Vec<_>>();
+        assert_eq!(rolls, [(1, "1d20+7".to_owned()), (2, "1d20+7".to_owned()), (1, "1d6".to_owned())]);
+
+        assert_eq!(Rolls::from_str_with("damage", resolve).unwrap_err(), ParseError::UnknownMacro("damage".to_owned()));
+        assert_eq!(Rolls::from_str_with("broken", resolve).unwrap_err(), ParseError::MissingFaces);
+    }
+}
@@ -1,20 +1,29 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use core::cmp;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
 
-use serenity::model::id::{UserId};
+use serenity::model::id::{UserId, RoleId, ChannelId, MessageId};
+use serenity::model::guild::Guild;
 use serenity::model::channel::Message;
+use serenity::model::Colour;
+use serenity::builder::{CreateEmbed, CreateMessage};
 use serenity::prelude::{Context};
-use serenity::framework::standard::{Args, CommandResult, CommandOptions, CheckResult, Check, HelpOptions, CommandGroup, help_commands, DispatchError, Reason};
-use serenity::framework::standard::macros::{command, group, help};
+use serenity::framework::standard::{Args, CommandResult, CommandOptions, HelpOptions, CommandGroup, help_commands, DispatchError, Reason};
+use serenity::framework::standard::macros::{check, command, group, help, hook};
 
-use crate::{utils, config};
+use crate::{config, logger, dice, rng, initiative};
+use crate::config::discord::{Subscription, RollRecord, Waifu, RoleMenu, MenuRole};
+use crate::discord::{reply, menu};
 use crate::stats::{self, STATS};
-use crate::constants::{Waifu, ADMIN_CHECK_FAIL, MSG_SET_WELCOME, MSG_REMOVE_WELCOME, MSG_REMOVE_SUB, MSG_ADD_SUB, MSG_UNKNOWN_SUB};
+use crate::constants::{ROLL_HISTORY_DISPLAY, MACROS_LIMIT, MSG_NO_ROLLS, MSG_NO_INITIATIVE, ADMIN_CHECK_FAIL, MSG_SET_WELCOME, MSG_REMOVE_WELCOME, MSG_SET_REPORTS, MSG_REMOVE_REPORTS, MSG_SET_TICKETS, MSG_REMOVE_TICKETS, MSG_REMOVE_SUB, MSG_ADD_SUB, MSG_UNKNOWN_SUB};
 
 macro_rules! handle_msg_send {
     ($res:expr) => {
         match $res {
             Ok(_) => Ok(()),
-            Err(serenity::Error::Http(error)) => match *error {
+            Err(serenity::Error::Http(error)) => match error {
                 serenity::prelude::HttpError::UnsuccessfulRequest(_) => {
                     STATS.increment(stats::DiscordMsgReject);
                     Err(error.into())
@@ -32,50 +41,492 @@ macro_rules! handle_msg_send {
     }
 }
 
-static IS_ADMIN_CHECK: Check = Check {
-    name: "Admin",
-    function: is_admin,
-    check_in_help: true,
-    display_in_help: false
-};
+//Discord limits message to 2000 characters, reply is prefixed with mention and ticket
+const MESSAGE_MAX_LEN: usize = 1850;
+const EMBED_FIELD_MAX_LEN: usize = 1024;
+const EMBED_FIELD_NAME_MAX_LEN: usize = 256;
+//Discord limits total length of embed's title, fields and footer
+const EMBED_MAX_LEN: usize = 6000;
+const SEEDS_DISPLAY: usize = 5;
+//Roll's ticket appended to result
+const TICKET_MAX_LEN: usize = 48;
+//Keeps `ROLL_HISTORY_DISPLAY` records within embed's description
+const ROLL_RECORD_MAX_LEN: usize = 150;
 
-fn is_admin(ctx: &mut Context, message: &Message, _args: &mut Args, _options: &CommandOptions) -> CheckResult {
+fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(now) => now.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+///Returns maximum length of each field's name and value, so that embed with `title` fits into its limit.
+fn embed_field_len(title: &str, fields: usize) -> usize {
+    (EMBED_MAX_LEN - title.chars().count()) / cmp::max(fields, 1)
+}
+
+///Returns whether rolls in guild of message are shown with ticket.
+fn shows_tickets(msg: &Message) -> bool {
+    match msg.guild_id {
+        Some(guild) => config::DISCORD.with_read(|config| config.tickets.contains(&guild.get())),
+        None => false,
+    }
+}
+
+///Returns ticket to append to roll, if it is shown.
+fn ticket_suffix(is_shown: bool, ticket: rng::Ticket) -> String {
+    match is_shown {
+        true => format!(" `{}`", ticket),
+        false => String::new(),
+    }
+}
+
+///Returns name and value of roll's embed field, fitting both into `field_len`.
+fn roll_field(name: String, breakdown: String, ticket: &str, field_len: usize) -> (String, String) {
+    let name = truncate(name, cmp::min(EMBED_FIELD_NAME_MAX_LEN, field_len / 2));
+    let value_len = cmp::min(EMBED_FIELD_MAX_LEN, field_len.saturating_sub(name.chars().count())).saturating_sub(TICKET_MAX_LEN);
+
+    let value = format!("{}{}", truncate(breakdown, value_len), ticket);
+    (name, value)
+}
+
+///Truncates text to fit into `max` characters.
+fn truncate(mut text: String, max: usize) -> String {
+    if let Some((idx, _)) = text.char_indices().nth(max) {
+        let idx = text[..idx].char_indices().next_back().map(|(idx, _)| idx).unwrap_or(0);
+        text.truncate(idx);
+        text.push('…');
+    }
+
+    text
+}
+
+async fn is_from_admin(ctx: &Context, message: &Message) -> bool {
     let owner_id = config::DISCORD.with_read(|config| config.owner);
-    if owner_id == message.author.id.0 {
-        return CheckResult::Success;
+    if owner_id == message.author.id.get() {
+        return true;
     }
 
-    if let Some(member) = message.member(&ctx.cache) {
-        if let Ok(permissions) = member.permissions(&ctx.cache) {
-            return permissions.administrator().into();
-        }
+    let member = match message.member(ctx).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+
+    match message.guild(&ctx.cache) {
+        Some(guild) => match guild.channels.get(&message.channel_id) {
+            Some(channel) => guild.user_permissions_in(channel, &member).administrator(),
+            None => false,
+        },
+        None => false,
     }
+}
 
-    CheckResult::Failure(Reason::Unknown)
+#[check]
+#[name = "Admin"]
+#[check_in_help(true)]
+#[display_in_help(false)]
+async fn is_admin(ctx: &Context, message: &Message, _args: &mut Args, _options: &CommandOptions) -> Result<(), Reason> {
+    match is_from_admin(ctx, message).await {
+        true => Ok(()),
+        false => Err(Reason::Unknown),
+    }
 }
 
 #[group("general")]
-#[commands(ping, dice, subscribe, set_waifu)]
+#[commands(ping, dice, rolls, initiative, seed, verify, subscribe, set_waifu)]
 #[description = "List of commands available for everyone"]
 pub struct General;
 
 #[command]
 #[description = "Ping bot and get pong in response, if bot is alvie"]
 #[max_args(0)]
-fn ping(ctx: &mut Context, msg: &Message) -> CommandResult {
-    handle_msg_send!(msg.reply(ctx, "Pong!"))
+async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
+    handle_msg_send!(reply(ctx, msg, "Pong!").await)
 }
 
 #[command]
-#[description = "Rolls DnD dice"]
-#[example = "Example: 2d20+2, d4-1"]
+#[description = "Rolls DnD dice\n\
+\n\
+Supports multiple terms and per dice modifiers:\n\
+\n\
+- `kh<n>`, `kl<n>` - keep highest/lowest `n` dice\n\
+- `dh<n>`, `dl<n>` - drop highest/lowest `n` dice\n\
+- `!` - explode on maximum face\n\
+- `r<n>` - reroll once dice showing `n` or lower\n\
+- `adv`, `dis` - d20 with advantage/disadvantage\n\
+\n\
+Multiple rolls are separated by comma, prefix `<n>x` repeats roll `n` times.\n\
+\n\
+Macros:\n\
+\n\
+- `save <name> <roll>` - saves your macro, that can be rolled by name\n\
+- `delete <name>` - deletes your macro\n\
+- `share <name> <roll>`, `unshare <name>` - saves or deletes macro for everyone in server, admin only\n\
+- `list` - lists available macros\n\
+"]
+#[example = "Example: 2d20+2, d4-1, 4d6dl1, adv+5, 1d8+2d6+3, 6x 4d6dl1, save attack 1d20+7"]
 #[min_args(1)]
-fn dice(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
-    let roll = args.rest();
+async fn dice(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let text = args.rest();
+
+    let mut split = text.splitn(2, char::is_whitespace);
+    let (cmd, rest) = (split.next().unwrap_or(""), split.next().unwrap_or("").trim());
+    match cmd.to_ascii_lowercase().as_str() {
+        "save" => return dice_save(ctx, msg, rest, false).await,
+        "share" => return dice_save(ctx, msg, rest, true).await,
+        "delete" => return dice_delete(ctx, msg, rest, false).await,
+        "unshare" => return dice_delete(ctx, msg, rest, true).await,
+        "list" => return dice_list(ctx, msg).await,
+        _ => (),
+    }
+
+    let (user, guild) = (msg.author.id.get(), msg.guild_id.map(|guild| guild.get()));
+    let resolve = |name: &str| config::DISCORD.with_read(|config| config.dice_macro(user, guild, name).map(str::to_owned));
+
+    let rolls = match dice::Rolls::from_str_with(text, resolve) {
+        Ok(rolls) => rolls,
+        Err(error) => return handle_msg_send!(reply(ctx, msg, format!("Cannot parse your roll: {}. Try better", error)).await),
+    };
+
+    let mut records = Vec::with_capacity(rolls.len());
+    let mut record = |outcome: &dice::Outcome, breakdown: &str| records.push((RollRecord {
+        time: unix_time(),
+        expr: outcome.expr.to_string(),
+        result: truncate(breakdown.to_owned(), ROLL_RECORD_MAX_LEN),
+        total: outcome.total,
+    }, outcome.naturals()));
+
+    let is_ticket_shown = shows_tickets(msg);
+    let res = match rolls.len() {
+        1 => {
+            let (ticket, mut random) = rng::commit();
+            let outcome = dice::Outcome::roll_with(&rolls.rolls[0].1, || random.gen());
+            record(&outcome, &dice::Breakdown(&outcome).to_string());
+            reply(ctx, msg, format!("{}{}", truncate(format!("You roll {}", outcome), MESSAGE_MAX_LEN), ticket_suffix(is_ticket_shown, ticket))).await
+        },
+        num => {
+            let title = format!("{} rolls", msg.author.name);
+            let field_len = embed_field_len(&title, num);
+            let mut embed = CreateEmbed::new().title(title).colour(Colour::DARK_RED);
+
+            for (count, expr) in rolls.rolls.iter() {
+                for idx in 1..=*count {
+                    let (ticket, mut random) = rng::commit();
+                    let outcome = dice::Outcome::roll_with(expr, || random.gen());
+                    let breakdown = dice::Breakdown(&outcome).to_string();
+                    let name = match count {
+                        1 => expr.to_string(),
+                        _ => format!("{} #{}", expr, idx),
+                    };
+
+                    record(&outcome, &breakdown);
+                    let (name, value) = roll_field(name, breakdown, &ticket_suffix(is_ticket_shown, ticket), field_len);
+                    embed = embed.field(name, value, true);
+                }
+            }
+
+            msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await
+        },
+    };
+
+    //Roll, that user hasn't seen, is not part of history
+    if res.is_ok() {
+        let key = (msg.author.id.get(), msg.channel_id.get());
+        config::DISCORD.with_write(|config| {
+            let history = config.rolls.entry(key).or_default();
+            for (record, naturals) in records {
+                history.push(record, naturals);
+            }
+        });
+    }
+
+    handle_msg_send!(res)
+}
+
+//Names, that cannot be used for macros
+const DICE_SUBCOMMANDS: &[&str] = &["save", "share", "delete", "unshare", "list"];
+
+///Returns id of macros' owner, that is either author or guild.
+async fn macros_owner(ctx: &Context, msg: &Message, is_guild: bool) -> Result<u64, &'static str> {
+    match is_guild {
+        true => match msg.guild_id {
+            Some(guild) => match is_from_admin(ctx, msg).await {
+                true => Ok(guild.get()),
+                false => Err(ADMIN_CHECK_FAIL),
+            },
+            None => Err("Shared macros are available in guild only"),
+        },
+        false => Ok(msg.author.id.get()),
+    }
+}
+
+async fn dice_save(ctx: &Context, msg: &Message, text: &str, is_guild: bool) -> CommandResult {
+    let mut split = text.splitn(2, char::is_whitespace);
+    let (name, body) = (split.next().unwrap_or(""), split.next().unwrap_or("").trim());
+
+    if !dice::is_macro_name(name) || DICE_SUBCOMMANDS.iter().any(|cmd| cmd.eq_ignore_ascii_case(name)) {
+        return handle_msg_send!(reply(ctx, msg, "Macro's name should start with letter and contain only letters, digits and `_`").await);
+    }
+
+    let expr = match dice::Expr::from_str(body) {
+        Ok(expr) => expr,
+        Err(error) => return handle_msg_send!(reply(ctx, msg, format!("Cannot parse your roll: {}. Try better", error)).await),
+    };
+
+    let owner = match macros_owner(ctx, msg, is_guild).await {
+        Ok(owner) => owner,
+        Err(error) => return handle_msg_send!(reply(ctx, msg, error).await),
+    };
+
+    let name = name.to_ascii_lowercase();
+    let body = expr.to_string();
+    let is_saved = config::DISCORD.with_write(|config| {
+        let macros = match is_guild {
+            true => config.guild_macros.entry(owner).or_default(),
+            false => config.macros.entry(owner).or_default(),
+        };
+
+        match macros.len() >= MACROS_LIMIT && !macros.contains_key(&name) {
+            true => false,
+            false => {
+                macros.insert(name.clone(), body.clone());
+                true
+            }
+        }
+    });
+
+    let res = match is_saved {
+        true => reply(ctx, msg, format!("Saved macro `{}` as `{}`", name, body)).await,
+        false => reply(ctx, msg, format!("There can be no more than {} macros", MACROS_LIMIT)).await,
+    };
+
+    handle_msg_send!(res)
+}
+
+async fn dice_delete(ctx: &Context, msg: &Message, name: &str, is_guild: bool) -> CommandResult {
+    let owner = match macros_owner(ctx, msg, is_guild).await {
+        Ok(owner) => owner,
+        Err(error) => return handle_msg_send!(reply(ctx, msg, error).await),
+    };
+
+    let name = name.to_ascii_lowercase();
+    let is_deleted = config::DISCORD.with_write(|config| {
+        let all = match is_guild {
+            true => &mut config.guild_macros,
+            false => &mut config.macros,
+        };
+
+        let is_deleted = match all.get_mut(&owner) {
+            Some(macros) => macros.remove(&name).is_some(),
+            None => false,
+        };
+
+        if all.get(&owner).map(|macros| macros.is_empty()).unwrap_or(false) {
+            all.remove(&owner);
+        }
+
+        is_deleted
+    });
+
+    let res = match is_deleted {
+        true => reply(ctx, msg, format!("Deleted macro `{}`", name)).await,
+        false => reply(ctx, msg, format!("There is no macro `{}`", name)).await,
+    };
+
+    handle_msg_send!(res)
+}
+
+async fn dice_list(ctx: &Context, msg: &Message) -> CommandResult {
+    let (user, guild) = (msg.author.id.get(), msg.guild_id.map(|guild| guild.get()));
+
+    let text = config::DISCORD.with_read(|config| {
+        let mut text = String::new();
+
+        if let Some(macros) = config.macros.get(&user) {
+            text.push_str("Your macros:\n");
+            for (name, body) in macros.iter() {
+                let _ = writeln!(text, "`{}` - `{}`", name, body);
+            }
+        }
+
+        if let Some(macros) = guild.and_then(|guild| config.guild_macros.get(&guild)) {
+            text.push_str("Server macros:\n");
+            for (name, body) in macros.iter() {
+                let _ = writeln!(text, "`{}` - `{}`", name, body);
+            }
+        }
+
+        text
+    });
+
+    let res = match text.is_empty() {
+        true => reply(ctx, msg, "There are no macros yet").await,
+        false => reply(ctx, msg, truncate(text, MESSAGE_MAX_LEN)).await,
+    };
+
+    handle_msg_send!(res)
+}
+
+#[command]
+#[aliases("init")]
+#[description = "Tracks initiative in this channel\n\
+\n\
+- `add <name> <modifier> [hp]` - adds combatant\n\
+- `remove <name>` - removes combatant\n\
+- `roll` - rolls initiative for everyone and starts combat, can be verified as `<n>d20` in previous order\n\
+- `set <name> <initiative>` - sets initiative manually\n\
+- `next` - passes turn to the next combatant\n\
+- `hp <name> <+n|-n|n>` - changes or sets HP\n\
+- `cond <name> <condition>` - adds or removes condition\n\
+- `clear` - ends combat, removing everyone\n\
+\n\
+Without arguments shows initiative order."]
+#[example = "add Goblin +2 7"]
+async fn initiative(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let channel = msg.channel_id.get();
+    let args = args.rest().split_whitespace().collect::<Vec<_>>();
+    let cmd = args.first().map(|cmd| cmd.to_ascii_lowercase()).unwrap_or_default();
+
+    if cmd.is_empty() {
+        let res = match config::DISCORD.with_read(|config| config.initiative.get(&channel).map(|tracker| tracker.to_string())) {
+            Some(order) => reply(ctx, msg, format!("```\n{}```", truncate(order, MESSAGE_MAX_LEN))).await,
+            None => reply(ctx, msg, MSG_NO_INITIATIVE).await,
+        };
+
+        return handle_msg_send!(res);
+    }
+
+    let is_ticket_shown = shows_tickets(msg);
+    let text = config::DISCORD.with_write(|config| {
+        let tracker = config.initiative.entry(channel).or_default();
+
+        //`None` on invalid arguments
+        let res = match (cmd.as_str(), &args[1..]) {
+            ("add", [name, modifier]) => modifier.parse().ok().map(|modifier| tracker.add(name, modifier, None).map(|_| format!("Added {}", name))),
+            ("add", [name, modifier, hp]) => match (modifier.parse(), hp.parse()) {
+                (Ok(modifier), Ok(hp)) => Some(tracker.add(name, modifier, Some(hp)).map(|_| format!("Added {}", name))),
+                _ => None,
+            },
+            ("remove", [name]) => Some(tracker.remove(name).map(|combatant| format!("Removed {}", combatant.name))),
+            ("roll", []) => {
+                let (ticket, mut random) = rng::commit();
+                Some(tracker.roll(|| dice::d20_with(|| random.gen())).map(|_| format!("Initiative is rolled{}", ticket_suffix(is_ticket_shown, ticket))))
+            },
+            ("set", [name, value]) => value.parse().ok().map(|value| tracker.set(name, value).map(|_| format!("Set initiative of {}", name))),
+            ("next", []) => Some(tracker.next().map(|combatant| format!("{}'s turn", combatant.name))),
+            ("hp", [name, change]) => initiative::Hp::from_str(change).map(|change| tracker.hp(name, change).map(|(hp, max)| format!("{} has {}/{} HP", name, hp, max))),
+            ("cond", [name, condition @ ..]) if !condition.is_empty() => {
+                let condition = condition.join(" ");
+                Some(tracker.toggle_condition(name, &condition).map(|is_added| match is_added {
+                    true => format!("{} is {}", name, condition),
+                    false => format!("{} is no longer {}", name, condition),
+                }))
+            },
+            ("clear", []) => {
+                tracker.combatants.clear();
+                Some(Ok("Combat is over".to_owned()))
+            },
+            _ => None,
+        };
+
+        let text = match res {
+            Some(Ok(text)) => match tracker.is_empty() {
+                true => text,
+                false => format!("{}\n```\n{}```", text, truncate(tracker.to_string(), MESSAGE_MAX_LEN)),
+            },
+            Some(Err(error)) => error.to_string(),
+            None => "Invalid arguments, check `~help initiative`".to_owned(),
+        };
+
+        if tracker.is_empty() {
+            config.initiative.remove(&channel);
+        }
+
+        text
+    });
+
+    handle_msg_send!(reply(ctx, msg, text).await)
+}
+
+#[command]
+#[description = "Shows commitment to current dice seed and recently revealed seeds\n\
+\n\
+Commitment is SHA-256 of seed, which is revealed once dice are reseeded.\n\
+Administrators can enable tickets of rolls in server with `tickets`.\n\
+Roll `#<epoch>.<nonce>` uses first 8 bytes of SHA-256 of seed followed by little endian `nonce` as state of wyhash generator."]
+#[max_args(0)]
+async fn seed(ctx: &Context, msg: &Message) -> CommandResult {
+    let (epoch, commitment) = rng::commitment();
+
+    let mut text = format!("Current epoch `{}`, commitment `{}`\n", epoch, rng::Hex(&commitment));
+    for (epoch, seed) in rng::revealed().iter().take(SEEDS_DISPLAY) {
+        let _ = writeln!(text, "Epoch `{}`, seed `{}`", epoch, rng::Hex(seed));
+    }
+
+    handle_msg_send!(reply(ctx, msg, text).await)
+}
+
+#[command]
+#[description = "Repeats roll, once seed of its epoch is revealed"]
+#[example = "#1600000000.12 4d6dl1"]
+#[min_args(2)]
+async fn verify(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let ticket = match args.single::<String>().ok().and_then(|ticket| rng::Ticket::from_str(&ticket)) {
+        Some(ticket) => ticket,
+        None => return handle_msg_send!(reply(ctx, msg, "Roll should be specified as `#<epoch>.<nonce>`").await),
+    };
+
+    let expr = match dice::Expr::from_str(args.rest()) {
+        Ok(expr) => expr,
+        Err(error) => return handle_msg_send!(reply(ctx, msg, format!("Cannot parse your roll: {}. Try better", error)).await),
+    };
 
-    let res = match cute_dnd_dice::Roll::from_str(roll) {
-        Ok(roll) => msg.reply(ctx, &format!("You roll {}", roll.roll())),
-        Err(error) => msg.reply(ctx, &format!("Cannot parse your roll: {}. Try better", error)),
+    let res = match rng::replay(ticket) {
+        Ok(mut random) => {
+            let outcome = dice::Outcome::roll_with(&expr, || random.gen());
+            reply(ctx, msg, truncate(format!("Roll `{}` was {}", ticket, outcome), MESSAGE_MAX_LEN)).await
+        },
+        Err(rng::VerifyError::NotRevealed) => reply(ctx, msg, "Seed of this roll is not revealed yet").await,
+        Err(rng::VerifyError::Unknown) => reply(ctx, msg, "I no longer remember seed of this roll").await,
+    };
+
+    handle_msg_send!(res)
+}
+
+#[command]
+#[description = "Shows your last rolls in this channel\n\
+\n\
+Use `stats` to show statistics of your rolls instead"]
+#[example = "stats"]
+#[max_args(1)]
+async fn rolls(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let key = (msg.author.id.get(), msg.channel_id.get());
+
+    let res = match args.current() {
+        Some(arg) if arg.eq_ignore_ascii_case("stats") => match config::DISCORD.with_read(|config| config.rolls.get(&key).map(|history| history.stats.to_string())) {
+            Some(stats) => reply(ctx, msg, format!("Your rolls in this channel:\n```\n{}```", stats)).await,
+            None => reply(ctx, msg, MSG_NO_ROLLS).await,
+        },
+        Some(_) => reply(ctx, msg, "I only know about `stats` of rolls").await,
+        None => {
+            let last = config::DISCORD.with_read(|config| config.rolls.get(&key).map(|history| {
+                let mut text = String::new();
+                for record in history.last.iter().rev().take(ROLL_HISTORY_DISPLAY) {
+                    let _ = writeln!(text, "<t:{}:R> `{}` → {}", record.time, record.expr, record.result);
+                }
+                text
+            }));
+
+            match last {
+                Some(last) => {
+                    let embed = CreateEmbed::new().title(format!("{} last rolls", msg.author.name))
+                                                  .colour(Colour::DARK_RED)
+                                                  .description(last);
+                    msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await
+                },
+                None => reply(ctx, msg, MSG_NO_ROLLS).await,
+            }
+        }
     };
 
     handle_msg_send!(res)
@@ -92,129 +543,519 @@ Available subscribtions:\n\
 - Bisokuzenshin - Subscribes to Azur Lane Slow Ahead 4koma TLs\n\
 "]
 #[num_args(1)]
-fn subscribe(ctx: &mut Context, msg: &Message, mut args: Args) -> CommandResult {
+async fn subscribe(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let arg = match args.trimmed().quoted().current() {
         Some(arg) => arg,
         None => unreach!()
     };
 
-    let ch_id = msg.channel_id.0;
+    let ch_id = msg.channel_id.get();
 
-    let text = if arg.eq_ignore_ascii_case("Naze") {
-        config::DISCORD.with_write(|config| match config.channels.naze.take(&ch_id).is_some() {
-            true => MSG_REMOVE_SUB,
-            false => {
-                config.channels.naze.insert(ch_id);
-                MSG_ADD_SUB
-            }
-        })
-    } else if arg.eq_ignore_ascii_case("Bisokuzenshin") {
-        config::DISCORD.with_write(|config| match config.channels.bisokuzenshin.take(&ch_id).is_some() {
-            true => MSG_REMOVE_SUB,
-            false => {
-                config.channels.bisokuzenshin.insert(ch_id);
-                MSG_ADD_SUB
-            }
-        })
-    } else {
-        MSG_UNKNOWN_SUB
+    let text = match Subscription::from_str(arg) {
+        Some(sub) => match config::DISCORD.with_write(|config| config.channels.toggle_subscription(sub, ch_id)) {
+            true => MSG_ADD_SUB,
+            false => MSG_REMOVE_SUB,
+        },
+        None => MSG_UNKNOWN_SUB,
     };
 
-    handle_msg_send!(msg.reply(ctx, text))
+    handle_msg_send!(reply(ctx, msg, text).await)
 }
 
 #[group("admin")]
-#[commands(stats, debug, welcome)]
-#[checks(is_admin)]
+#[commands(stats, debug, welcome, reports, tickets, loglevel, roster, menu)]
+#[checks(Admin)]
 #[description = "List of commands available for administrators"]
 pub struct Admin;
 
 #[command]
-#[description = "List bot's counters"]
-#[max_args(0)]
-fn stats(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let res = msg.channel_id.send_message(&ctx.http, |msg| {
-            msg.embed(|embed| embed.title("Stats").color(serenity::utils::Colour::DARK_RED)
-                                   .field("Discord", &STATS.discord, true)
-                                   .field("Twitter", &STATS.twitter, true))
-    });
+#[description = "List bot's counters\n\
+\n\
+Use `commands` to list per command usage instead"]
+#[example = "commands"]
+#[max_args(1)]
+async fn stats(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match args.current() {
+        Some(arg) if arg.eq_ignore_ascii_case("commands") => {
+            let commands = stats::COMMANDS.take_delta();
+            let embed = CreateEmbed::new().title("Commands since last time").colour(Colour::DARK_RED)
+                                          .field("By name", stats::ByName(&commands).to_string(), false)
+                                          .field("By guild", stats::ByGuild(&commands).to_string(), false);
 
-    STATS.reset();
+            handle_msg_send!(msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await)
+        },
+        Some(_) => handle_msg_send!(reply(ctx, msg, "I only know about `commands` stats").await),
+        None => {
+            let counters = STATS.take_delta();
+            let latency = stats::LATENCY.take_delta();
+            let embed = CreateEmbed::new().title("Stats since last time").colour(Colour::DARK_RED)
+                                          .field("Discord", counters.group("discord").to_string(), true)
+                                          .field("Twitter", counters.group("twitter").to_string(), true)
+                                          .field("Latency", latency.to_string(), false);
 
-    handle_msg_send!(res)
+            handle_msg_send!(msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await)
+        }
+    }
 }
 
 #[command]
-#[description = "Select your waifu"]
+#[description = "Select your waifu among server's roster"]
 #[max_args(1)]
-fn set_waifu(ctx: &mut Context, msg: &Message, args: Args) -> CommandResult {
+async fn set_waifu(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.get(),
+        None => return handle_msg_send!(reply(&*ctx.http, msg, "This command is available in guild only").await),
+    };
+
+    let roster = config::DISCORD.with_read(|config| config.waifus(guild_id).to_vec());
+
     let waifu = match args.current() {
-        Some(waifu) => match Waifu::from_str(waifu) {
+        Some(name) => match roster.iter().find(|waifu| waifu.is_named(name)) {
             Some(waifu) => waifu,
             None => {
-                let res = msg.reply(&*ctx.http, "I don't know such girl.");
+                let res = reply(&*ctx.http, msg, "I don't know such girl.").await;
                 return handle_msg_send!(res)
             }
         },
         None => {
-            let res = msg.reply(&*ctx.http, "Who is your waifu among Rinne, Jeanne, Reiren and Hinemarill?");
+            let res = match roster.is_empty() {
+                true => reply(&*ctx.http, msg, "There are no waifus in this server.").await,
+                false => reply(&*ctx.http, msg, format!("Who is your waifu among {}?", list_names(roster.iter().map(|waifu| waifu.name.as_str())))).await,
+            };
             return handle_msg_send!(res)
         }
     };
 
-    let mut member = match msg.member(&ctx.cache) {
-        Some(member) => member,
-        None => {
-            let res = msg.reply(&*ctx.http, "This command is available in guild only");
+    let member = match msg.member(ctx).await {
+        Ok(member) => member,
+        Err(_) => {
+            let res = reply(&*ctx.http, msg, "This command is available in guild only").await;
             return handle_msg_send!(res)
         }
     };
 
-    let guild = match msg.guild(&ctx.cache) {
-        Some(guild) => guild,
+    let roles = msg.guild(&ctx.cache).map(|guild| waifu_roles(&role_positions(&guild), bot_position(&guild), &roster, waifu));
+    let roles = match roles {
+        Some(roles) => roles,
         None => {
-            let res = msg.reply(&*ctx.http, "This command is available in guild only");
+            let res = reply(&*ctx.http, msg, "This command is available in guild only").await;
             return handle_msg_send!(res)
         }
     };
 
-    let mut to_remove_roles = utils::four::Vec::<serenity::model::id::RoleId>::new();
-    let mut waifu_role = serenity::model::id::RoleId(0);
-    let waifu_str = waifu.as_str();
+    let (waifu_role, mut to_remove_roles) = match roles {
+        Ok(roles) => roles,
+        Err(error) => return handle_msg_send!(reply(&*ctx.http, msg, error.to_string()).await),
+    };
+
+    if member.roles.contains(&waifu_role) {
+        return handle_msg_send!(reply(&*ctx.http, msg, "Yes, I know that she is your waifu, you don't need to repeat").await);
+    }
+
+    to_remove_roles.retain(|role| member.roles.contains(role));
+    if !to_remove_roles.is_empty() {
+        if let Err(err) = member.remove_roles(&*ctx.http, to_remove_roles.as_slice()).await {
+            log::error!("Failed to remove roles. Error: {:?}", err);
+            return handle_msg_send!(reply(&*ctx.http, msg, "Cannot remove your previous waifu :(").await);
+        }
+    }
+
+    let res = match member.add_role(&*ctx.http, waifu_role).await {
+        Ok(_) => {
+            let embed = CreateEmbed::new().description(format!("Set waifu of {} as {} {}", msg.author.name, waifu.name, waifu.emoji)).colour(waifu.colour);
+            msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await
+        },
+        Err(err) => {
+            log::error!("Failed to set role. Error: {:?}", err);
+            reply(&*ctx.http, msg, "Cannot set waifu :(").await
+        }
+    };
+
+    return handle_msg_send!(res)
+}
+
+///Joins names as `A, B and C`.
+fn list_names<'a>(names: impl ExactSizeIterator<Item = &'a str>) -> String {
+    let len = names.len();
+    let mut res = String::new();
 
-    for (id, role) in guild.read().roles.iter() {
-        if !role.name.starts_with("Team") {
-            continue;
+    for (idx, name) in names.enumerate() {
+        match idx {
+            0 => (),
+            idx if idx + 1 == len => res.push_str(" and "),
+            _ => res.push_str(", "),
         }
+        res.push_str(name);
+    }
+
+    res
+}
+
+#[derive(Debug, PartialEq)]
+///Reasons, why role cannot be given by bot
+pub enum RoleError {
+    ///Waifu has no role
+    Unset(String),
+    ///Role doesn't exist in guild
+    Missing(u64),
+    ///Role is not below bot's highest role
+    TooHigh(u64),
+}
 
-        if role.name.ends_with(waifu_str) {
-            waifu_role = *id;
-        } else {
-            to_remove_roles.push(*id);
+impl core::fmt::Display for RoleError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            RoleError::Unset(name) => write!(f, "{} has no role yet, ask admin to set it with `~roster add`", name),
+            RoleError::Missing(role) => write!(f, "Role `{}` doesn't exist in this server", role),
+            RoleError::TooHigh(role) => write!(f, "Role <@&{}> should be below my highest role for me to give it", role),
         }
     }
+}
 
-    if member.roles.contains(&waifu_role) {
-        return handle_msg_send!(msg.reply(&*ctx.http, "Yes, I know that she is your waifu, you don't need to repeat"));
+///Returns position of bot's highest role in guild, `0` if it has none.
+pub fn bot_position(guild: &Guild) -> i64 {
+    let bot_id = UserId::new(super::SELF_ID.load(Ordering::Acquire));
+
+    guild.members.get(&bot_id)
+                 .and_then(|member| member.roles.iter().filter_map(|id| guild.roles.get(id)).map(|role| i64::from(role.position)).max())
+                 .unwrap_or(0)
+}
+
+///Returns roles of guild with their positions.
+pub fn role_positions(guild: &Guild) -> Vec<(RoleId, i64)> {
+    guild.roles.iter().map(|(id, role)| (*id, i64::from(role.position))).collect()
+}
+
+///Checks that role exists among `roles` with its position and is below `bot_position`.
+pub fn check_role(roles: &[(RoleId, i64)], bot_position: i64, role: u64) -> Result<RoleId, RoleError> {
+    match roles.iter().find(|(id, _)| id.get() == role) {
+        Some((id, position)) => match *position < bot_position {
+            true => Ok(*id),
+            false => Err(RoleError::TooHigh(role)),
+        },
+        None => Err(RoleError::Missing(role)),
     }
+}
 
-    let _ = member.remove_roles(&*ctx.http, to_remove_roles.as_slice());
-    let res = match member.add_role(&*ctx.http, waifu_role) {
-        Ok(_) => msg.reply(&*ctx.http, format!("Set your waifu as {}", waifu_str)),
-        Err(err) => {
-            rogu::error!("Failed to set role. Error: {:?}", err);
-            msg.reply(&*ctx.http, "Cannot set waifu :(")
+///Splits roles of roster into valid role of `waifu` and existing roles of other girls.
+fn waifu_roles(roles: &[(RoleId, i64)], bot_position: i64, roster: &[Waifu], waifu: &Waifu) -> Result<(RoleId, Vec<RoleId>), RoleError> {
+    let waifu_role = match waifu.role {
+        0 => return Err(RoleError::Unset(waifu.name.clone())),
+        role => check_role(roles, bot_position, role)?,
+    };
+
+    let to_remove_roles = roster.iter().filter(|other| other.role != 0 && other.role != waifu_role.get())
+                                .filter_map(|other| roles.iter().find(|(id, _)| id.get() == other.role))
+                                .map(|(id, _)| *id)
+                                .collect();
+
+    Ok((waifu_role, to_remove_roles))
+}
+
+///Maximum length of waifu's name and each of aliases
+const WAIFU_NAME_MAX_LEN: usize = 32;
+///Maximum number of waifu's aliases
+const WAIFU_ALIASES_LIMIT: usize = 5;
+
+///Returns description of waifu within roster, fitting it together with name into `field_len`.
+fn waifu_field(waifu: &Waifu, field_len: usize) -> String {
+    let mut text = match waifu.role {
+        0 => "Role: not set\n".to_owned(),
+        role => format!("Role: <@&{}>\n", role),
+    };
+    if !waifu.aliases.is_empty() {
+        let _ = writeln!(text, "Aliases: {}", waifu.aliases.join(", "));
+    }
+    if !waifu.emoji.is_empty() {
+        let _ = writeln!(text, "Emoji: {}", waifu.emoji);
+    }
+    if waifu.colour != 0 {
+        let _ = writeln!(text, "Colour: #{:06x}", waifu.colour);
+    }
+
+    truncate(text, cmp::min(EMBED_FIELD_MAX_LEN, field_len.saturating_sub(waifu.name.chars().count())))
+}
+
+///Parses `<name> role=<role> [emoji=<emoji>] [colour=<#rrggbb>] [aliases=<alias,...>]`
+fn parse_waifu(text: &str) -> Result<Waifu, String> {
+    let mut parts = text.split_whitespace();
+    let mut waifu = match parts.next() {
+        Some(name) if !name.contains('=') && name.chars().count() <= WAIFU_NAME_MAX_LEN => Waifu::new(name),
+        Some(_) => return Err(format!("Name should be no more than {} characters", WAIFU_NAME_MAX_LEN)),
+        None => return Err("Name is missing".to_owned()),
+    };
+
+    for part in parts {
+        let mut split = part.splitn(2, '=');
+        match (split.next().unwrap_or("").to_ascii_lowercase().as_str(), split.next()) {
+            ("role", Some(role)) => waifu.role = match serenity::utils::parse_role_mention(role).map(RoleId::get).or_else(|| role.parse().ok()) {
+                Some(0) | None => return Err(format!("Invalid role '{}'", role)),
+                Some(role) => role,
+            },
+            ("emoji", Some(emoji)) => waifu.emoji = emoji.to_owned(),
+            ("colour", Some(colour)) | ("color", Some(colour)) => waifu.colour = match u32::from_str_radix(colour.trim_start_matches('#'), 16) {
+                Ok(colour) if colour <= 0xffffff => colour,
+                _ => return Err(format!("Invalid colour '{}'", colour)),
+            },
+            ("aliases", Some(aliases)) => {
+                waifu.aliases = aliases.split(',').filter(|alias| !alias.is_empty()).map(str::to_lowercase).collect();
+                if waifu.aliases.len() > WAIFU_ALIASES_LIMIT {
+                    return Err(format!("No more than {} aliases are allowed", WAIFU_ALIASES_LIMIT));
+                }
+                if waifu.aliases.iter().any(|alias| alias.chars().count() > WAIFU_NAME_MAX_LEN) {
+                    return Err(format!("Alias should be no more than {} characters", WAIFU_NAME_MAX_LEN));
+                }
+            },
+            _ => return Err(format!("Unknown option '{}'", part)),
         }
+    }
+
+    match waifu.role {
+        0 => Err("Role is missing".to_owned()),
+        _ => Ok(waifu),
+    }
+}
+
+#[command]
+#[description = "Manages waifus of this server\n\
+\n\
+- `add <name> role=<role> [emoji=<emoji>] [colour=<#rrggbb>] [aliases=<alias,...>]` - adds or replaces waifu\n\
+- `remove <name>` - removes waifu\n\
+\n\
+Without arguments shows roster. Role should be below my highest role."]
+#[example = "add Kaguya role=@TeamKaguya emoji=🌸 colour=#d04060 aliases=shinomiya"]
+async fn roster(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.get(),
+        None => return handle_msg_send!(reply(ctx, msg, "This command is available in guild only").await),
     };
 
-    return handle_msg_send!(res)
+    let text = args.rest();
+    let mut split = text.splitn(2, char::is_whitespace);
+    let (cmd, rest) = (split.next().unwrap_or(""), split.next().unwrap_or("").trim());
+
+    let res = match cmd.to_ascii_lowercase().as_str() {
+        "" => {
+            let roster = config::DISCORD.with_read(|config| config.waifus(guild_id).to_vec());
+
+            match roster.is_empty() {
+                true => reply(ctx, msg, "There are no waifus in this server.").await,
+                false => {
+                    let field_len = embed_field_len("Waifus", roster.len());
+                    let embed = CreateEmbed::new().title("Waifus").colour(Colour::DARK_RED)
+                                                  .fields(roster.iter().map(|waifu| (waifu.name.as_str(), waifu_field(waifu, field_len), true)));
+
+                    msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await
+                },
+            }
+        },
+        "add" => match parse_waifu(rest).and_then(|waifu| match msg.guild(&ctx.cache) {
+            Some(guild) => check_role(&role_positions(&guild), bot_position(&guild), waifu.role).map(|_| waifu).map_err(|error| error.to_string()),
+            None => Err("I cannot see roles of this server".to_owned()),
+        }) {
+            Ok(waifu) => {
+                let name = waifu.name.clone();
+                match config::DISCORD.with_write(|config| config.add_waifu(guild_id, waifu)) {
+                    Ok(_) => reply(ctx, msg, format!("Added {} to waifus", name)).await,
+                    Err(error) => reply(ctx, msg, error.to_string()).await,
+                }
+            },
+            Err(error) => reply(ctx, msg, error).await,
+        },
+        "remove" => match config::DISCORD.with_write(|config| config.remove_waifu(guild_id, rest)) {
+            Some(waifu) => reply(ctx, msg, format!("Removed {} from waifus", waifu.name)).await,
+            None => reply(ctx, msg, "I don't know such girl.").await,
+        },
+        _ => reply(ctx, msg, "Invalid arguments, check `~help roster`").await,
+    };
+
+    handle_msg_send!(res)
+}
+
+///Maximum number of reactions per message
+const MENU_ROLES_LIMIT: usize = 20;
+///Maximum number of menus per guild
+const MENUS_LIMIT: usize = 10;
+
+///Parses pairs of `<emoji> <role>`
+fn parse_menu_roles(text: &str) -> Result<Vec<MenuRole>, String> {
+    let parts = text.split_whitespace().collect::<Vec<_>>();
+    let mut roles = Vec::with_capacity(parts.len() / 2);
+
+    if parts.is_empty() || parts.len() % 2 != 0 {
+        return Err("Menu needs pairs of emoji and role".to_owned());
+    } else if parts.len() / 2 > MENU_ROLES_LIMIT {
+        return Err(format!("Too many roles, no more than {} is allowed", MENU_ROLES_LIMIT));
+    }
+
+    for pair in parts.chunks(2) {
+        let (emoji, role) = (pair[0], pair[1]);
+
+        if serenity::utils::parse_role_mention(emoji).is_some() || emoji.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid emoji '{}'", emoji));
+        }
+
+        let role = match serenity::utils::parse_role_mention(role).map(RoleId::get).or_else(|| role.parse().ok()) {
+            Some(0) | None => return Err(format!("Invalid role '{}'", role)),
+            Some(role) => role,
+        };
+
+        let key = menu::reaction_key(&menu::parse_reaction(emoji));
+        if roles.iter().any(|other: &MenuRole| other.key == key || other.role == role) {
+            return Err(format!("Emoji '{}' or its role is used twice", emoji));
+        }
+
+        roles.push(MenuRole {
+            emoji: emoji.to_owned(),
+            key,
+            role,
+        });
+    }
+
+    Ok(roles)
+}
+
+///Posts menu in channel of message, remembering it.
+async fn post_menu(ctx: &Context, msg: &Message, guild_id: u64, exclusive: bool, roles: Vec<MenuRole>) -> serenity::Result<Message> {
+    let mut text = String::new();
+    for role in roles.iter() {
+        let _ = writeln!(text, "{} <@&{}>", role.emoji, role.role);
+    }
+
+    let embed = CreateEmbed::new().title(match exclusive {
+                                      true => "Pick your role",
+                                      false => "Pick your roles",
+                                  })
+                                  .colour(Colour::DARK_RED)
+                                  .description(text);
+    let menu_msg = msg.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await?;
+
+    for role in roles.iter() {
+        if let Err(error) = menu_msg.react(&ctx.http, menu::parse_reaction(&role.emoji)).await {
+            log::warn!("Cannot react to menu with {}. Error: {}", role.emoji, error);
+            let _ = menu_msg.delete(ctx).await;
+            return reply(ctx, msg, format!("Cannot react with {}, is it emoji of this server?", role.emoji)).await;
+        }
+    }
+
+    config::DISCORD.with_write(|config| config.menus.insert(menu_msg.id.get(), RoleMenu {
+        guild: guild_id,
+        channel: msg.channel_id.get(),
+        exclusive,
+        roles,
+    }));
+
+    Ok(menu_msg)
+}
+
+#[command]
+#[description = "Manages reaction role menus\n\
+\n\
+- `exclusive <emoji> <role> ...` - posts menu, where member can pick only one role\n\
+- `multi <emoji> <role> ...` - posts menu, where member can pick any roles\n\
+- `waifus` - posts exclusive menu of waifus with emoji\n\
+- `delete <message id>` - deletes menu\n\
+\n\
+Without arguments lists menus of this server, that can have up to 10 menus. Roles should be below my highest role."]
+#[example = "exclusive 🔥 @Fire 💧 @Water"]
+async fn menu(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.get(),
+        None => return handle_msg_send!(reply(ctx, msg, "This command is available in guild only").await),
+    };
+
+    let text = args.rest();
+    let mut split = text.splitn(2, char::is_whitespace);
+    let (cmd, rest) = (split.next().unwrap_or(""), split.next().unwrap_or("").trim());
+
+    let roles = match cmd.to_ascii_lowercase().as_str() {
+        "" => {
+            let mut text = String::new();
+            config::DISCORD.with_read(|config| {
+                for (id, menu) in config.menus.iter().filter(|(_, menu)| menu.guild == guild_id) {
+                    let mode = match menu.exclusive {
+                        true => "exclusive",
+                        false => "multi",
+                    };
+                    let _ = writeln!(text, "https://discord.com/channels/{}/{}/{} - {}, {} roles", guild_id, menu.channel, id, mode, menu.roles.len());
+                }
+            });
+
+            let res = match text.is_empty() {
+                true => reply(ctx, msg, "There are no menus in this server.").await,
+                false => reply(ctx, msg, truncate(text, MESSAGE_MAX_LEN)).await,
+            };
+            return handle_msg_send!(res);
+        },
+        "delete" => {
+            let menu = rest.parse().ok().and_then(|id| config::DISCORD.with_write(|config| match config.menus.get(&id) {
+                Some(menu) if menu.guild == guild_id => config.menus.remove(&id).map(|menu| (id, menu)),
+                _ => None,
+            }));
+
+            let res = match menu {
+                Some((id, menu)) => {
+                    if let Err(error) = ChannelId::new(menu.channel).delete_message(&ctx.http, MessageId::new(id)).await {
+                        log::warn!("Cannot delete menu message. Error: {}", error);
+                    }
+                    reply(ctx, msg, "Deleted menu").await
+                },
+                None => reply(ctx, msg, "There is no such menu in this server.").await,
+            };
+            return handle_msg_send!(res);
+        },
+        "waifus" => {
+            let roles = config::DISCORD.with_read(|config| {
+                config.waifus(guild_id).iter().filter(|waifu| waifu.role != 0 && !waifu.emoji.is_empty()).map(|waifu| MenuRole {
+                    emoji: waifu.emoji.clone(),
+                    key: menu::reaction_key(&menu::parse_reaction(&waifu.emoji)),
+                    role: waifu.role,
+                }).take(MENU_ROLES_LIMIT).collect::<Vec<_>>()
+            });
+
+            match roles.is_empty() {
+                true => Err("There are no waifus with role and emoji".to_owned()),
+                false => Ok((true, roles)),
+            }
+        },
+        "exclusive" => parse_menu_roles(rest).map(|roles| (true, roles)),
+        "multi" => parse_menu_roles(rest).map(|roles| (false, roles)),
+        _ => Err("Invalid arguments, check `~help menu`".to_owned()),
+    };
+
+    let menus_num = config::DISCORD.with_read(|config| config.menus.values().filter(|menu| menu.guild == guild_id).count());
+    let roles = match menus_num >= MENUS_LIMIT {
+        true => Err(format!("No more than {} menus are allowed in server, delete some first", MENUS_LIMIT)),
+        false => roles,
+    };
+
+    let roles = roles.and_then(|roles| match msg.guild(&ctx.cache) {
+        Some(guild) => {
+            let positions = role_positions(&guild);
+            let bot_position = bot_position(&guild);
+
+            match roles.1.iter().map(|role| check_role(&positions, bot_position, role.role)).find_map(Result::err) {
+                Some(error) => Err(error.to_string()),
+                None => Ok(roles),
+            }
+        },
+        None => Err("I cannot see roles of this server".to_owned()),
+    });
+
+    let res = match roles {
+        Ok((exclusive, roles)) => post_menu(ctx, msg, guild_id, exclusive, roles).await,
+        Err(error) => reply(ctx, msg, error).await,
+    };
+
+    handle_msg_send!(res)
 }
 
 #[command]
 #[description = "Debug bot"]
-fn debug(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let _ = msg.reply(&*ctx.http, format!("Raw msg: `{:?}`", msg.content.clone()));
-    let res = msg.reply(&*ctx.http, format!("Clean msg: `{:?}`", msg.content_safe(&ctx.cache)));
+async fn debug(ctx: &Context, msg: &Message) -> CommandResult {
+    let _ = reply(&*ctx.http, msg, format!("Raw msg: `{:?}`", msg.content.clone())).await;
+    let clean = serenity::utils::content_safe(&ctx.cache, &msg.content, &serenity::utils::ContentSafeOptions::default(), &msg.mentions);
+    let res = reply(&*ctx.http, msg, format!("Clean msg: `{:?}`", clean)).await;
 
     handle_msg_send!(res)
 }
@@ -222,21 +1063,69 @@ fn debug(ctx: &mut Context, msg: &Message) -> CommandResult {
 #[command]
 #[description = "Sets welcome channel for new users"]
 #[max_args(0)]
-fn welcome(ctx: &mut Context, msg: &Message) -> CommandResult {
-    let channel_id = msg.channel_id.0;
+async fn welcome(ctx: &Context, msg: &Message) -> CommandResult {
+    let channel_id = msg.channel_id.get();
 
-    let rsp = config::DISCORD.with_write(move |config| match config.channels.welcome == channel_id {
-        true => {
-            config.channels.welcome = 0;
-            MSG_REMOVE_WELCOME
-        },
-        false => {
-            config.channels.welcome = channel_id;
-            MSG_SET_WELCOME
+    let rsp = match config::DISCORD.with_write(|config| config.channels.toggle_welcome(channel_id)) {
+        true => MSG_SET_WELCOME,
+        false => MSG_REMOVE_WELCOME,
+    };
+
+    handle_msg_send!(reply(ctx, msg, rsp).await)
+}
+
+#[command]
+#[description = "Sets channel to report errors, instead of owner's DM"]
+#[max_args(0)]
+async fn reports(ctx: &Context, msg: &Message) -> CommandResult {
+    let channel_id = msg.channel_id.get();
+
+    let rsp = match config::DISCORD.with_write(|config| config.toggle_reports(channel_id)) {
+        true => MSG_SET_REPORTS,
+        false => MSG_REMOVE_REPORTS,
+    };
+
+    handle_msg_send!(reply(ctx, msg, rsp).await)
+}
+
+#[command]
+#[description = "Enables or disables tickets of rolls in this server, that allow to verify them"]
+#[max_args(0)]
+async fn tickets(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.get(),
+        None => return handle_msg_send!(reply(ctx, msg, "This command is available in guild only").await),
+    };
+
+    let rsp = match config::DISCORD.with_write(|config| config.toggle_tickets(guild_id)) {
+        true => MSG_SET_TICKETS,
+        false => MSG_REMOVE_TICKETS,
+    };
+
+    handle_msg_send!(reply(ctx, msg, rsp).await)
+}
+
+#[command]
+#[description = "Shows or changes logging level\n\
+\n\
+Accepts comma separated list of `level` or `module=level`.\n\
+Levels: off, error, warn, info, debug, trace"]
+#[example = "debug, jeanne::twitter=trace"]
+async fn loglevel(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let spec = args.rest();
+
+    let res = match spec.is_empty() {
+        true => reply(ctx, msg, format!("Logging level: `{}`", logger::filter())).await,
+        false => match logger::update_filter(spec) {
+            Ok(filter) => {
+                log::info!("Logging level is changed to '{}'", filter);
+                reply(ctx, msg, format!("Logging level is set to `{}`", filter)).await
+            },
+            Err(error) => reply(ctx, msg, format!("Cannot change logging level: {}", error)).await,
         },
-    });
+    };
 
-    handle_msg_send!(msg.reply(ctx, rsp))
+    handle_msg_send!(res)
 }
 
 #[help]
@@ -246,24 +1135,55 @@ fn welcome(ctx: &mut Context, msg: &Message) -> CommandResult {
 #[lacking_permissions = "strike"]
 #[lacking_role = "strike"]
 #[lacking_ownership = "strike"]
-fn help(context: &mut Context, msg: &Message, args: Args, help_options: &'static HelpOptions, groups: &[&'static CommandGroup], owners: HashSet<UserId>) -> CommandResult {
-    help_commands::with_embeds(context, msg, args, help_options, groups, owners)
+async fn help(context: &Context, msg: &Message, args: Args, help_options: &'static HelpOptions, groups: &[&'static CommandGroup], owners: HashSet<UserId>) -> CommandResult {
+    let _ = help_commands::with_embeds(context, msg, args, help_options, groups, owners).await;
+    Ok(())
+}
+
+lazy_static::lazy_static! {
+    //Command may be resumed on other thread, so its start is kept by message
+    static ref CMD_START: parking_lot::Mutex<HashMap<MessageId, Instant>> = parking_lot::Mutex::new(HashMap::new());
 }
 
-pub fn on_dispatch_error(ctx: &mut serenity::prelude::Context, message: &serenity::model::channel::Message, error: DispatchError) {
+#[hook]
+pub async fn before_command(_: &Context, message: &Message, _: &str) -> bool {
+    STATS.increment(stats::DiscordCmdNum);
+    CMD_START.lock().insert(message.id, Instant::now());
+
+    true
+}
+
+#[hook]
+pub async fn after_command(_: &Context, message: &Message, name: &str, res: CommandResult) {
+    let elapsed = match CMD_START.lock().remove(&message.id) {
+        Some(start) => start.elapsed(),
+        None => Default::default(),
+    };
+
+    if let Err(error) = res.as_ref() {
+        log::warn!("Command '{}' failed: {:?}", name, error);
+    }
+
+    let guild = message.guild_id.map(|guild| guild.get()).unwrap_or(0);
+    stats::COMMANDS.record(name, guild, res.is_err(), elapsed);
+}
+
+#[hook]
+pub async fn on_dispatch_error(ctx: &Context, message: &Message, error: DispatchError, _: &str) {
     let res = match error {
-        DispatchError::Ratelimited(remaining) => {
+        DispatchError::Ratelimited(info) => {
+            let remaining = info.rate_limit.as_secs();
             let minutes = remaining / 60;
             let seconds = remaining % 60;
 
             match minutes {
-                0 => message.reply(ctx, &format!("Please wait just a bit more. Remains {} seconds", remaining)),
-                _ => message.reply(ctx, &format!("You shouldn't do it so much. Wait {}:{} minutes", minutes, seconds)),
+                0 => reply(ctx, message, format!("Please wait just a bit more. Remains {} seconds", remaining)).await,
+                _ => reply(ctx, message, format!("You shouldn't do it so much. Wait {}:{} minutes", minutes, seconds)).await,
             }
         }
-        DispatchError::NotEnoughArguments {min, given} => message.reply(ctx, &format!("Command needs at least {} arguments, {} were given", min, given)),
-        DispatchError::TooManyArguments {max, given} => message.reply(ctx, &format!("Command needs no more than {} arguments, {} were given", max, given)),
-        DispatchError::CheckFailed("Admin", _) => message.reply(ctx, ADMIN_CHECK_FAIL),
+        DispatchError::NotEnoughArguments {min, given} => reply(ctx, message, format!("Command needs at least {} arguments, {} were given", min, given)).await,
+        DispatchError::TooManyArguments {max, given} => reply(ctx, message, format!("Command needs no more than {} arguments, {} were given", max, given)).await,
+        DispatchError::CheckFailed("Admin", _) => reply(ctx, message, ADMIN_CHECK_FAIL).await,
         _ => return,
     };
 
@@ -274,3 +1194,137 @@ pub fn on_dispatch_error(ctx: &mut serenity::prelude::Context, message: &serenit
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{DEFAULT_WAIFUS, ROSTER_LIMIT};
+
+    #[test]
+    fn should_truncate_text() {
+        assert_eq!(truncate("short".to_owned(), 5), "short");
+        assert_eq!(truncate("longer".to_owned(), 5), "long…");
+        assert_eq!(truncate("→→→→→→".to_owned(), 3), "→→…");
+    }
+
+    #[test]
+    fn should_fit_fields_into_embed() {
+        let title = "Jeanne rolls";
+        let ticket = rng::Ticket {
+            epoch: u64::MAX,
+            nonce: u64::MAX,
+        };
+
+        let ticket = ticket_suffix(true, ticket);
+        assert_eq!(ticket_suffix(false, rng::Ticket { epoch: 1, nonce: 2 }), "");
+
+        let field_len = embed_field_len(title, 12);
+        let (name, value) = roll_field("1d20+".repeat(100), "→".repeat(2000), &ticket, field_len);
+        assert!(name.chars().count() <= EMBED_FIELD_NAME_MAX_LEN);
+        assert!(value.ends_with(&ticket));
+        assert!(title.len() + 12 * (name.chars().count() + value.chars().count()) <= EMBED_MAX_LEN);
+
+        let (name, value) = roll_field("1d20".to_owned(), "→".repeat(2000), &ticket, embed_field_len(title, 2));
+        assert_eq!(name, "1d20");
+        assert!(value.chars().count() <= EMBED_FIELD_MAX_LEN);
+
+        //Too many fields leave no room for breakdown, but must not underflow
+        let (_, value) = roll_field("1d20".to_owned(), "→".repeat(2000), &ticket, embed_field_len(title, 1000));
+        assert_eq!(value, format!("…{}", ticket));
+    }
+
+    fn roster() -> Vec<Waifu> {
+        DEFAULT_WAIFUS.iter().zip(1..).map(|(name, role)| {
+            let mut waifu = Waifu::new(name);
+            waifu.role = role;
+            waifu
+        }).collect()
+    }
+
+    #[test]
+    fn should_split_waifu_roles() {
+        let roles = [(RoleId::new(1), 1), (RoleId::new(2), 2), (RoleId::new(3), 3), (RoleId::new(5), 5)];
+        let roster = roster();
+
+        let (waifu, others) = waifu_roles(&roles, 10, &roster, &roster[1]).expect("To find roles");
+        assert_eq!(waifu, RoleId::new(2));
+        //Missing role of other waifu is skipped
+        assert_eq!(others.as_slice(), &[RoleId::new(1), RoleId::new(3)]);
+    }
+
+    #[test]
+    fn should_validate_waifu_role() {
+        let roles = [(RoleId::new(1), 1), (RoleId::new(2), 5), (RoleId::new(3), 6)];
+        let mut roster = roster();
+
+        assert_eq!(waifu_roles(&roles, 5, &roster, &roster[0]).map(|(role, _)| role), Ok(RoleId::new(1)));
+        assert_eq!(waifu_roles(&roles, 5, &roster, &roster[1]), Err(RoleError::TooHigh(2)));
+        assert_eq!(waifu_roles(&roles, 5, &roster, &roster[2]), Err(RoleError::TooHigh(3)));
+        assert_eq!(waifu_roles(&roles, 5, &roster, &roster[3]), Err(RoleError::Missing(4)));
+
+        roster[0].role = 0;
+        assert_eq!(waifu_roles(&roles, 5, &roster, &roster[0]), Err(RoleError::Unset("Rinne".to_owned())));
+        assert_eq!(check_role(&roles, 0, 1), Err(RoleError::TooHigh(1)));
+    }
+
+    #[test]
+    fn should_parse_waifu() {
+        let waifu = parse_waifu("Kaguya role=<@&42> emoji=🌸 colour=#d04060 aliases=Shinomiya,kaguya_sama").expect("To parse waifu");
+        assert_eq!(waifu.name, "Kaguya");
+        assert_eq!(waifu.role, 42);
+        assert_eq!(waifu.emoji, "🌸");
+        assert_eq!(waifu.colour, 0xd04060);
+        assert_eq!(waifu.aliases, ["shinomiya", "kaguya_sama"]);
+
+        assert_eq!(parse_waifu("Chika role=7").map(|waifu| waifu.role), Ok(7));
+        assert!(parse_waifu("Chika").is_err());
+        assert!(parse_waifu("Chika role=0").is_err());
+        assert!(parse_waifu("").is_err());
+        assert!(parse_waifu("Chika role=chika").is_err());
+        assert!(parse_waifu("Chika colour=#1000000").is_err());
+        assert!(parse_waifu("Chika hair=pink").is_err());
+        assert!(parse_waifu("Chika role=7 aliases=a,b,c,d,e").is_ok());
+        assert!(parse_waifu("Chika role=7 aliases=a,b,c,d,e,f").is_err());
+        assert!(parse_waifu(&format!("Chika role=7 aliases={}", "a".repeat(WAIFU_NAME_MAX_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn should_fit_roster_into_embed() {
+        let roster = (0..ROSTER_LIMIT).map(|idx| {
+            let mut waifu = Waifu::new(&format!("{}{}", "W".repeat(WAIFU_NAME_MAX_LEN - 2), idx));
+            waifu.role = idx as u64 + 1;
+            waifu.emoji = "🌸".repeat(1000);
+            waifu.aliases = vec!["a".repeat(WAIFU_NAME_MAX_LEN); WAIFU_ALIASES_LIMIT];
+            waifu
+        }).collect::<Vec<_>>();
+
+        let field_len = embed_field_len("Waifus", roster.len());
+        let total = roster.iter().map(|waifu| waifu.name.chars().count() + waifu_field(waifu, field_len).chars().count()).sum::<usize>();
+        assert!("Waifus".len() + total <= EMBED_MAX_LEN);
+        assert!(waifu_field(&roster[0], field_len).starts_with("Role: <@&1>\nAliases: "));
+    }
+
+    #[test]
+    fn should_parse_menu_roles() {
+        let roles = parse_menu_roles("🔥 <@&10> <:JeanneSmile:715486276207378503> 11").expect("To parse menu");
+        assert_eq!(roles.len(), 2);
+        assert_eq!((roles[0].emoji.as_str(), roles[0].key.as_str(), roles[0].role), ("🔥", "🔥", 10));
+        assert_eq!((roles[1].key.as_str(), roles[1].role), ("715486276207378503", 11));
+
+        assert!(parse_menu_roles("").is_err());
+        assert!(parse_menu_roles("🔥").is_err());
+        assert!(parse_menu_roles("<@&10> 🔥").is_err());
+        assert!(parse_menu_roles("fire <@&10>").is_err());
+        assert!(parse_menu_roles("🔥 fire").is_err());
+        assert!(parse_menu_roles("🔥 <@&10> 🔥 <@&11>").is_err());
+        assert!(parse_menu_roles("🔥 <@&10> 💧 <@&10>").is_err());
+        assert!(parse_menu_roles(&"🔥 1 ".repeat(MENU_ROLES_LIMIT + 1)).is_err());
+    }
+
+    #[test]
+    fn should_list_names() {
+        assert_eq!(list_names(["Rinne"].iter().cloned()), "Rinne");
+        assert_eq!(list_names(["Rinne", "Jeanne"].iter().cloned()), "Rinne and Jeanne");
+        assert_eq!(list_names(DEFAULT_WAIFUS.iter().cloned()), "Rinne, Jeanne, Reiren and Hinemarill");
+    }
+}
@@ -0,0 +1,133 @@
+use serenity::model::channel::{Reaction, ReactionType};
+use serenity::model::id::{GuildId, MessageId, RoleId, UserId};
+use serenity::prelude::Context;
+
+use crate::config;
+use super::stat_serenity_error;
+use super::commands::{bot_position, check_role, role_positions};
+
+///Returns reaction for emoji as written in message
+pub fn parse_reaction(text: &str) -> ReactionType {
+    match serenity::utils::parse_emoji(text) {
+        Some(emoji) => emoji.into(),
+        None => ReactionType::Unicode(text.to_owned()),
+    }
+}
+
+///Returns key, identifying emoji of reaction
+pub fn reaction_key(reaction: &ReactionType) -> String {
+    match reaction {
+        ReactionType::Custom { id, .. } => id.get().to_string(),
+        ReactionType::Unicode(text) => text.clone(),
+        _ => String::new(),
+    }
+}
+
+///Gives role of menu, removing roles it replaces.
+pub async fn on_reaction_add(ctx: &Context, reaction: &Reaction, user: UserId) {
+    let key = reaction_key(&reaction.emoji);
+    let pick = config::DISCORD.with_read(|config| config.menus.get(&reaction.message_id.get()).and_then(|menu| {
+        menu.pick(&key).map(|(role, others)| (menu.guild, role.role, others.into_iter().cloned().collect::<Vec<_>>()))
+    }));
+
+    let (guild, role, others) = match pick {
+        Some(pick) => pick,
+        None => return,
+    };
+
+    //Roles could be moved above bot since menu is posted
+    let checked = match GuildId::new(guild).to_guild_cached(&ctx.cache) {
+        Some(cached) => {
+            check_role(&role_positions(&cached), bot_position(&cached), role).map_err(|error| error.to_string())
+        },
+        None => Err(format!("Guild {} is not cached", guild)),
+    };
+
+    if let Err(error) = checked {
+        log::warn!("Cannot give menu role {}: {}", role, error);
+        return;
+    }
+
+    let member = match GuildId::new(guild).member(ctx, user).await {
+        Ok(member) => member,
+        Err(error) => {
+            log::warn!("Cannot get member {} of guild {}: {}", user, guild, error);
+            return;
+        }
+    };
+
+    for other in others.iter() {
+        if !member.roles.contains(&RoleId::new(other.role)) {
+            continue;
+        }
+
+        if let Err(error) = member.remove_role(&ctx.http, RoleId::new(other.role)).await {
+            log::error!("Cannot remove menu role {}. Error: {}", other.role, error);
+            stat_serenity_error(&error);
+        }
+
+        //Menu is exclusive, so only reaction of current role is kept
+        if let Err(error) = reaction.channel_id.delete_reaction(&ctx.http, reaction.message_id, Some(user), parse_reaction(&other.emoji)).await {
+            log::warn!("Cannot remove reaction {}. Error: {}", other.emoji, error);
+        }
+    }
+
+    if !member.roles.contains(&RoleId::new(role)) {
+        if let Err(error) = member.add_role(&ctx.http, RoleId::new(role)).await {
+            log::error!("Cannot add menu role {}. Error: {}", role, error);
+            stat_serenity_error(&error);
+        }
+    }
+}
+
+///Removes role of menu.
+pub async fn on_reaction_remove(ctx: &Context, reaction: &Reaction, user: UserId) {
+    let key = reaction_key(&reaction.emoji);
+    let role = config::DISCORD.with_read(|config| config.menus.get(&reaction.message_id.get()).and_then(|menu| {
+        menu.role(&key).map(|role| (menu.guild, role.role))
+    }));
+
+    let (guild, role) = match role {
+        Some(role) => role,
+        None => return,
+    };
+
+    let member = match GuildId::new(guild).member(ctx, user).await {
+        Ok(member) => member,
+        Err(error) => {
+            log::warn!("Cannot get member {} of guild {}: {}", user, guild, error);
+            return;
+        }
+    };
+
+    if member.roles.contains(&RoleId::new(role)) {
+        if let Err(error) = member.remove_role(&ctx.http, RoleId::new(role)).await {
+            log::error!("Cannot remove menu role {}. Error: {}", role, error);
+            stat_serenity_error(&error);
+        }
+    }
+}
+
+///Forgets menu, once its message is deleted.
+pub fn on_message_delete(message: MessageId) {
+    let is_menu = config::DISCORD.with_read(|config| config.menus.contains_key(&message.get()));
+
+    if is_menu {
+        config::DISCORD.with_write(|config| config.menus.remove(&message.get()));
+        log::info!("Menu {} is deleted", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_identify_reactions() {
+        let custom = parse_reaction("<:JeanneSmile:715486276207378503>");
+        assert_eq!(reaction_key(&custom), "715486276207378503");
+
+        let unicode = parse_reaction("🔥");
+        assert_eq!(reaction_key(&unicode), "🔥");
+    }
+}
@@ -0,0 +1,379 @@
+//Local stand-in for discord gateway and REST API.
+
+use std::io;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use serenity::http::{Http, HttpBuilder};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use std::sync::Arc;
+
+pub const BOT: u64 = 1000;
+pub const OWNER: u64 = 1001;
+pub const USER: u64 = 1002;
+///Channel, where bot is not allowed to send messages
+pub const FORBIDDEN: u64 = 1003;
+
+const TIMESTAMP: &str = "2020-01-01T00:00:00.000000+00:00";
+const TIMEOUT: core::time::Duration = core::time::Duration::from_secs(10);
+
+#[derive(Debug)]
+///Request received by REST endpoint.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+pub fn user(id: u64, name: &str, bot: bool) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": name,
+        "discriminator": "0000",
+        "global_name": null,
+        "avatar": null,
+        "bot": bot,
+    })
+}
+
+pub fn role(id: u64, name: &str, position: u16, permissions: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "name": name,
+        "color": 0,
+        "hoist": false,
+        "icon": null,
+        "unicode_emoji": null,
+        "position": position,
+        "permissions": permissions.to_string(),
+        "managed": false,
+        "mentionable": false,
+    })
+}
+
+pub fn member(guild: u64, user: Value, roles: &[u64]) -> Value {
+    json!({
+        "guild_id": guild.to_string(),
+        "user": user,
+        "nick": null,
+        "avatar": null,
+        "roles": roles.iter().map(|role| role.to_string()).collect::<Vec<_>>(),
+        "joined_at": TIMESTAMP,
+        "premium_since": null,
+        "deaf": false,
+        "mute": false,
+        "flags": 0,
+        "pending": false,
+    })
+}
+
+pub fn channel(guild: u64, id: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "guild_id": guild.to_string(),
+        "type": 0,
+        "name": "general",
+        "position": 0,
+        "permission_overwrites": [],
+        "nsfw": false,
+    })
+}
+
+///Creates guild with `roles`, `members` and text `channels`.
+///
+///First role is expected to be `@everyone`, sharing id with guild.
+pub fn guild(id: u64, roles: Vec<Value>, members: Vec<Value>, channels: &[u64]) -> Value {
+    json!({
+        "id": id.to_string(),
+        "name": "Mock",
+        "icon": null,
+        "splash": null,
+        "discovery_splash": null,
+        "owner_id": OWNER.to_string(),
+        "afk_channel_id": null,
+        "afk_timeout": 300,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "explicit_content_filter": 0,
+        "roles": roles,
+        "emojis": [],
+        "features": [],
+        "mfa_level": 0,
+        "application_id": null,
+        "system_channel_id": null,
+        "system_channel_flags": 0,
+        "rules_channel_id": null,
+        "vanity_url_code": null,
+        "description": null,
+        "banner": null,
+        "premium_tier": 0,
+        "preferred_locale": "en-US",
+        "public_updates_channel_id": null,
+        "nsfw_level": 0,
+        "premium_progress_bar_enabled": false,
+        "stickers": [],
+        "joined_at": TIMESTAMP,
+        "large": false,
+        "unavailable": false,
+        "member_count": members.len(),
+        "members": members,
+        "channels": channels.iter().map(|ch| channel(id, *ch)).collect::<Vec<_>>(),
+        "threads": [],
+        "presences": [],
+        "voice_states": [],
+        "stage_instances": [],
+        "guild_scheduled_events": [],
+    })
+}
+
+///Creates message, sent by `author` in guild's channel.
+pub fn message(id: u64, guild: u64, channel: u64, author: Value, content: &str) -> Value {
+    json!({
+        "id": id.to_string(),
+        "channel_id": channel.to_string(),
+        "guild_id": guild.to_string(),
+        "author": author,
+        "content": content,
+        "timestamp": TIMESTAMP,
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    })
+}
+
+fn application() -> Value {
+    json!({
+        "id": BOT.to_string(),
+        "name": "Jeanne",
+        "icon": null,
+        "description": "",
+        "bot_public": true,
+        "bot_require_code_grant": false,
+        "owner": user(OWNER, "owner", false),
+        "verify_key": "",
+        "team": null,
+        "flags": 0,
+    })
+}
+
+fn ready(guild: u64, gateway: &str) -> Value {
+    json!({
+        "v": 10,
+        "user": user(BOT, "Jeanne", true),
+        "guilds": [{"id": guild.to_string(), "unavailable": true}],
+        "session_id": "mock",
+        "resume_gateway_url": gateway,
+        "shard": [0, 1],
+        "application": {"id": BOT.to_string(), "flags": 0},
+    })
+}
+
+///Returns status and body of REST response to request.
+fn route(method: &str, path: &str, gateway: &str) -> (&'static str, Value) {
+    let path = path.trim_start_matches("/api/v10");
+    let parts = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+
+    match (method, parts.as_slice()) {
+        ("GET", ["gateway"]) => ("200 OK", json!({"url": gateway})),
+        ("GET", ["gateway", "bot"]) => ("200 OK", json!({
+            "url": gateway,
+            "shards": 1,
+            "session_start_limit": {"total": 1000, "remaining": 1000, "reset_after": 0, "max_concurrency": 1},
+        })),
+        ("GET", ["users", "@me"]) => ("200 OK", user(BOT, "Jeanne", true)),
+        ("GET", ["oauth2", "applications", "@me"]) => ("200 OK", application()),
+        ("POST", ["channels", channel, "messages"]) if channel.parse() == Ok(FORBIDDEN) => ("403 Forbidden", json!({"message": "Missing Permissions", "code": 50013})),
+        ("POST", ["channels", channel, "messages"]) => {
+            let mut sent = message(1, 0, channel.parse().unwrap_or(0), user(BOT, "Jeanne", true), "");
+            //Ids cannot be zero
+            sent["guild_id"] = Value::Null;
+            ("200 OK", sent)
+        },
+        ("PUT", ["guilds", _, "members", _, "roles", _]) | ("DELETE", ["guilds", _, "members", _, "roles", _]) => ("204 No Content", Value::Null),
+        ("PUT", ["channels", _, "messages", _, "reactions", ..]) | ("DELETE", ["channels", _, "messages", _, "reactions", ..]) => ("204 No Content", Value::Null),
+        _ => ("404 Not Found", json!({"message": "Unknown", "code": 0})),
+    }
+}
+
+async fn handle_rest(stream: TcpStream, gateway: String, requests: mpsc::UnboundedSender<Request>) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_owned();
+    let path = parts.next().unwrap_or("").to_owned();
+
+    let mut len = 0;
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await?;
+
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+
+    let (status, response) = route(&method, &path, &gateway);
+    let _ = requests.send(Request {
+        method,
+        path,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+
+    let response = match response {
+        Value::Null => String::new(),
+        response => response.to_string(),
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response.len(), response);
+
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+fn dispatch(seq: &mut u64, event: &str, data: Value) -> WsMessage {
+    *seq += 1;
+    WsMessage::Text(json!({"op": 0, "s": *seq, "t": event, "d": data}).to_string())
+}
+
+async fn handle_gateway(stream: TcpStream, gateway: String, guild: Value, events: Arc<Mutex<mpsc::UnboundedReceiver<(&'static str, Value)>>>) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    let mut seq = 0;
+    let mut identified = false;
+
+    ws.send(WsMessage::Text(json!({"op": 10, "d": {"heartbeat_interval": 45000}}).to_string())).await?;
+
+    let mut events = events.lock().await;
+    loop {
+        tokio::select! {
+            frame = ws.next() => {
+                let frame = match frame {
+                    Some(frame) => frame?,
+                    None => break,
+                };
+
+                let payload = match frame {
+                    WsMessage::Text(text) => serde_json::from_str::<Value>(&text).unwrap_or(Value::Null),
+                    WsMessage::Close(_) => break,
+                    _ => continue,
+                };
+
+                match payload["op"].as_u64() {
+                    //Identify
+                    Some(2) => {
+                        let guild_id = guild["id"].as_str().and_then(|id| id.parse().ok()).unwrap_or(0);
+                        ws.send(dispatch(&mut seq, "READY", ready(guild_id, &gateway))).await?;
+                        ws.send(dispatch(&mut seq, "GUILD_CREATE", guild.clone())).await?;
+                        identified = true;
+                    },
+                    //Heartbeat
+                    Some(1) => ws.send(WsMessage::Text(json!({"op": 11}).to_string())).await?,
+                    _ => (),
+                }
+            },
+            //Events are held until bot is ready
+            event = events.recv(), if identified => match event {
+                Some((event, data)) => ws.send(dispatch(&mut seq, event, data)).await?,
+                None => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///Mock of discord, serving single guild.
+pub struct Mock {
+    rest: String,
+    requests: mpsc::UnboundedReceiver<Request>,
+    events: mpsc::UnboundedSender<(&'static str, Value)>,
+}
+
+impl Mock {
+    ///Starts gateway and REST servers on current runtime.
+    ///
+    ///Gateway sends `guild` on identify.
+    pub async fn start(guild: Value) -> Self {
+        let rest = TcpListener::bind("127.0.0.1:0").await.expect("To bind REST");
+        let gateway = TcpListener::bind("127.0.0.1:0").await.expect("To bind gateway");
+        let rest_url = format!("http://{}", rest.local_addr().expect("REST address"));
+        let gateway_url = format!("ws://{}", gateway.local_addr().expect("Gateway address"));
+
+        let (requests_tx, requests) = mpsc::unbounded_channel();
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let events_rx = Arc::new(Mutex::new(events_rx));
+
+        let url = gateway_url.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = rest.accept().await {
+                tokio::spawn(handle_rest(stream, url.clone(), requests_tx.clone()));
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = gateway.accept().await {
+                if let Err(error) = handle_gateway(stream, gateway_url.clone(), guild.clone(), events_rx.clone()).await {
+                    log::warn!("Mock gateway error: {}", error);
+                }
+            }
+        });
+
+        Self {
+            rest: rest_url,
+            requests,
+            events,
+        }
+    }
+
+    ///Returns HTTP client, sending all requests to mock.
+    pub fn http(&self) -> Http {
+        HttpBuilder::new("mock").proxy(self.rest.as_str()).ratelimiter_disabled(true).build()
+    }
+
+    ///Sends gateway event to bot.
+    pub fn dispatch(&self, event: &'static str, data: Value) {
+        self.events.send((event, data)).expect("Gateway to run");
+    }
+
+    ///Waits for request to `path`, skipping all others.
+    pub async fn expect(&mut self, method: &str, path: &str) -> Request {
+        let path = format!("/api/v10{}", path);
+
+        let wait = async {
+            while let Some(request) = self.requests.recv().await {
+                if request.method == method && request.path == path {
+                    return request;
+                }
+            }
+
+            panic!("REST server is stopped");
+        };
+
+        match tokio::time::timeout(TIMEOUT, wait).await {
+            Ok(request) => request,
+            Err(_) => panic!("No {} {} within {:?}", method, path, TIMEOUT),
+        }
+    }
+}
@@ -1,39 +1,92 @@
-use crate::{config, constants};
+//Standard framework is deprecated in favour of poise, but commands are built on it
+#![allow(deprecated)]
+
+use crate::{config, constants, health};
 use crate::stats::{self, STATS};
-use crate::twitter;
 
 use std::sync::Arc;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+use serenity::async_trait;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, EmojiId, GuildId, MessageId};
+
 mod commands;
+mod menu;
+#[cfg(test)]
+mod mock;
 
 use commands::*;
 
 static SELF_ID: AtomicU64 = AtomicU64::new(0);
 
+type HttpWatch = (tokio::sync::watch::Sender<Option<Arc<Http>>>, tokio::sync::watch::Receiver<Option<Arc<Http>>>);
+
 lazy_static::lazy_static! {
-    pub static ref HTTP: parking_lot::RwLock<Option<Arc<serenity::CacheAndHttp>>> = parking_lot::RwLock::new(None);
+    static ref HTTP: HttpWatch = tokio::sync::watch::channel(None);
+    static ref SHARD_MANAGER: parking_lot::RwLock<Option<Arc<serenity::gateway::ShardManager>>> = parking_lot::RwLock::new(None);
+}
+
+///Waits until discord is running, returning its HTTP client.
+pub async fn wait_http() -> Arc<Http> {
+    let mut http = HTTP.1.clone();
+
+    loop {
+        let current = http.borrow_and_update().clone();
+        if let Some(http) = current {
+            return http;
+        }
+
+        //Sender is static, so it is never closed
+        let _ = http.changed().await;
+    }
+}
+
+fn set_http(http: Option<Arc<Http>>) {
+    HTTP.0.send_replace(http);
+}
+
+///Returns number of connected shards and total number of shards.
+pub async fn shards_status() -> (usize, usize) {
+    use serenity::gateway::ConnectionStage;
+
+    let manager = SHARD_MANAGER.read().clone();
+    match manager {
+        Some(manager) => {
+            let runners = manager.runners.lock().await;
+            let connected = runners.values().filter(|runner| matches!(runner.stage, ConnectionStage::Connected)).count();
+
+            (connected, runners.len())
+        },
+        None => (0, 0),
+    }
+}
+
+///Replies to message, recording latency.
+#[inline]
+pub async fn reply(cache_http: impl serenity::http::CacheHttp, msg: &serenity::model::channel::Message, content: impl Into<String>) -> serenity::Result<serenity::model::channel::Message> {
+    stats::LATENCY.discord_reply.time(msg.reply(cache_http, content)).await
+}
+
+///Sends message to channel, recording latency.
+#[inline]
+pub async fn say(http: impl serenity::http::CacheHttp, channel: ChannelId, content: impl Into<String>) -> serenity::Result<serenity::model::channel::Message> {
+    stats::LATENCY.discord_say.time(channel.say(http, content)).await
 }
 
 #[inline(always)]
 fn get_reaction_server_emoji(id: u64, name: &str) -> serenity::model::channel::ReactionType {
-    serenity::model::misc::EmojiIdentifier {
-        id: id.into(),
-        name: name.into(),
-    }.into()
+    serenity::model::channel::ReactionType::Custom {
+        animated: false,
+        id: EmojiId::new(id),
+        name: Some(name.into()),
+    }
 }
 
 fn stat_serenity_error(error: &serenity::Error) {
-    use core::ops::Deref;
-
     match error {
-        serenity::Error::Http(ref error) => match error.deref() {
-            serenity::prelude::HttpError::UnsuccessfulRequest(_) => {
-                STATS.increment(stats::DiscordMsgReject);
-            },
-            _ => {
-                STATS.increment(stats::DiscordMsgFail);
-            }
+        serenity::Error::Http(serenity::prelude::HttpError::UnsuccessfulRequest(_)) => {
+            STATS.increment(stats::DiscordMsgReject);
         },
         _ => {
             STATS.increment(stats::DiscordMsgFail);
@@ -53,19 +106,22 @@ impl Handler {
     }
 }
 
+#[async_trait]
 impl serenity::client::EventHandler for Handler {
-    fn ready(&self, ctx: serenity::prelude::Context, _bot_data: serenity::model::gateway::Ready) {
+    async fn ready(&self, ctx: serenity::prelude::Context, bot_data: serenity::model::gateway::Ready) {
         STATS.increment(stats::DiscordConnected);
+        STATS.set(stats::DiscordGuilds, bot_data.guilds.len());
+        health::set_discord_connected(true);
 
-        if !self.welcome_done.compare_and_swap(false, true, Ordering::AcqRel) {
+        if self.welcome_done.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_ok() {
             let welcome_channel = config::DISCORD.with_read(|config| config.channels.welcome);
 
             if welcome_channel > 0 {
-                let welcome_channel = serenity::model::id::ChannelId(welcome_channel);
-                match welcome_channel.say(&ctx.http, constants::JEANNE_GREETING) {
+                let welcome_channel = ChannelId::new(welcome_channel);
+                match say(&ctx.http, welcome_channel, constants::JEANNE_GREETING).await {
                     Ok(_) => (),
                     Err(error) => {
-                        rogu::error!("Unable to greet on discord. Error: {}", error);
+                        log::error!("Unable to greet on discord. Error: {}", error);
                         stat_serenity_error(&error);
                     }
                 }
@@ -73,28 +129,48 @@ impl serenity::client::EventHandler for Handler {
         }
     }
 
-    fn resume(&self, _ctx: serenity::prelude::Context, _: serenity::model::event::ResumedEvent) {
+    async fn resume(&self, _ctx: serenity::prelude::Context, _: serenity::model::event::ResumedEvent) {
         STATS.increment(stats::DiscordReConnected);
+        health::set_discord_connected(true);
     }
 
-    fn guild_member_addition(&self, ctx: serenity::prelude::Context, _: serenity::model::id::GuildId, user: serenity::model::guild::Member) {
-        use serenity::model::misc::Mentionable;
+    async fn shard_stage_update(&self, _ctx: serenity::prelude::Context, event: serenity::gateway::ShardStageUpdateEvent) {
+        match event.new {
+            serenity::gateway::ConnectionStage::Connected => health::set_discord_connected(true),
+            _ => health::set_discord_connected(false),
+        }
+    }
 
-        let welcome_channel = config::DISCORD.with_read(|config| config.channels.welcome);
-        if welcome_channel > 0 {
-            let welcome_channel = serenity::model::id::ChannelId(welcome_channel);
+    async fn guild_create(&self, _: serenity::prelude::Context, guild: serenity::model::guild::Guild, _: Option<bool>) {
+        let guild_id = guild.id.get();
+        let is_unset = config::DISCORD.with_read(|config| config.waifus(guild_id).iter().any(|waifu| waifu.role == 0));
+        if !is_unset {
+            return;
+        }
 
-            let mention = {
-                let user = user.user.read();
+        let roles = guild.roles.values().filter(|role| role.name.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("team"))).map(|role| (role.id.get(), role.name.as_str())).collect::<Vec<_>>();
+        if roles.is_empty() {
+            return;
+        }
 
-                if user.bot {
-                    return;
-                }
+        let num = config::DISCORD.with_write(|config| config.fill_waifu_roles(guild_id, &roles));
+        if num > 0 {
+            log::info!("Filled {} waifu roles of guild {} from Team roles", num, guild_id);
+        }
+    }
 
-                user.id.mention()
-            };
+    async fn guild_member_addition(&self, ctx: serenity::prelude::Context, member: serenity::model::guild::Member) {
+        use serenity::model::mention::Mentionable;
+
+        if member.user.bot {
+            return;
+        }
+
+        let welcome_channel = config::DISCORD.with_read(|config| config.channels.welcome);
+        if welcome_channel > 0 {
+            let welcome_channel = ChannelId::new(welcome_channel);
 
-            match welcome_channel.say(&ctx.http, format_args!("@here Everyone, please welcome {}", mention)) {
+            match say(&ctx.http, welcome_channel, format!("@here Everyone, please welcome {}", member.user.id.mention())).await {
                 Ok(_) => (),
                 Err(error) => stat_serenity_error(&error),
             }
@@ -103,7 +179,7 @@ impl serenity::client::EventHandler for Handler {
         STATS.increment(stats::DiscordNewMember);
     }
 
-    fn guild_member_removal(&self, _: serenity::prelude::Context, _: serenity::model::id::GuildId, user: serenity::model::user::User, _: Option<serenity::model::guild::Member>) {
+    async fn guild_member_removal(&self, _: serenity::prelude::Context, _: GuildId, user: serenity::model::user::User, _: Option<serenity::model::guild::Member>) {
         if user.bot {
             return;
         }
@@ -111,29 +187,57 @@ impl serenity::client::EventHandler for Handler {
         STATS.increment(stats::DiscordLossMember);
     }
 
-    fn message(&self, ctx: serenity::prelude::Context, msg: serenity::model::prelude::Message) {
+    async fn reaction_add(&self, ctx: serenity::prelude::Context, reaction: serenity::model::channel::Reaction) {
+        let self_id = SELF_ID.load(Ordering::Acquire);
+
+        match reaction.user_id {
+            Some(user) if self_id != 0 && user.get() != self_id => menu::on_reaction_add(&ctx, &reaction, user).await,
+            _ => (),
+        }
+    }
+
+    async fn reaction_remove(&self, ctx: serenity::prelude::Context, reaction: serenity::model::channel::Reaction) {
+        let self_id = SELF_ID.load(Ordering::Acquire);
+
+        match reaction.user_id {
+            Some(user) if self_id != 0 && user.get() != self_id => menu::on_reaction_remove(&ctx, &reaction, user).await,
+            _ => (),
+        }
+    }
+
+    async fn message_delete(&self, _: serenity::prelude::Context, _: ChannelId, message: MessageId, _: Option<GuildId>) {
+        menu::on_message_delete(message);
+    }
+
+    async fn message_delete_bulk(&self, _: serenity::prelude::Context, _: ChannelId, messages: Vec<MessageId>, _: Option<GuildId>) {
+        for message in messages {
+            menu::on_message_delete(message);
+        }
+    }
+
+    async fn message(&self, ctx: serenity::prelude::Context, msg: serenity::model::prelude::Message) {
         if msg.author.bot {
             return;
         }
 
         let self_id = SELF_ID.load(Ordering::Acquire);
 
-        if self_id == 0 || msg.author.id.0 == self_id {
+        if self_id == 0 || msg.author.id.get() == self_id {
             return;
         }
 
         if msg.mention_everyone {
-            if let Err(error) = msg.react(&*ctx.http, get_reaction_server_emoji(constants::emoji::jeanne::hmph::ID, constants::emoji::jeanne::hmph::NAME)) {
-                rogu::error!("Cannot react with hmph. Error={}", error);
+            if let Err(error) = msg.react(&ctx, get_reaction_server_emoji(constants::emoji::jeanne::hmph::ID, constants::emoji::jeanne::hmph::NAME)).await {
+                log::error!("Cannot react with hmph. Error={}", error);
                 stat_serenity_error(&error);
             }
             return;
         }
 
         if msg.mentions.len() == 1 {
-            if msg.mentions[0].id.0 == self_id {
-                if let Err(error) = msg.react(&*ctx.http, get_reaction_server_emoji(constants::emoji::jeanne::smile::ID, constants::emoji::jeanne::smile::NAME)) {
-                    rogu::error!("Cannot react with smile. Error={}", error);
+            if msg.mentions[0].id.get() == self_id {
+                if let Err(error) = msg.react(&ctx, get_reaction_server_emoji(constants::emoji::jeanne::smile::ID, constants::emoji::jeanne::smile::NAME)).await {
+                    log::error!("Cannot react with smile. Error={}", error);
                     stat_serenity_error(&error);
                 }
             }
@@ -141,65 +245,212 @@ impl serenity::client::EventHandler for Handler {
     }
 }
 
-fn configure(config: &mut serenity::framework::standard::Configuration) -> &mut serenity::framework::standard::Configuration {
-    config.prefix(config::CMD_PREFIX)
-          .ignore_bots(true)
-          .case_insensitivity(true)
-          .allow_dm(true)
+fn configure() -> serenity::framework::standard::Configuration {
+    serenity::framework::standard::Configuration::new().prefix(config::CMD_PREFIX)
+                                                       .ignore_bots(true)
+                                                       .case_insensitivity(true)
+                                                       .allow_dm(true)
 }
 
-pub fn run() {
-    let mut client = serenity::client::Client::new(config::DISCORD_TOKEN, Handler::new()).expect("To create client");
+fn intents() -> serenity::model::gateway::GatewayIntents {
+    use serenity::model::gateway::GatewayIntents;
+
+    //Members and message content are privileged, so they must be enabled in developer portal
+    GatewayIntents::GUILDS | GatewayIntents::GUILD_MEMBERS | GatewayIntents::GUILD_MESSAGES | GatewayIntents::GUILD_MESSAGE_REACTIONS
+    | GatewayIntents::DIRECT_MESSAGES | GatewayIntents::MESSAGE_CONTENT
+}
 
-    client.with_framework(
-        serenity::framework::StandardFramework::new().configure(configure)
-                                                     .help(&HELP)
-                                                     .on_dispatch_error(on_dispatch_error)
-                                                     .group(&GENERAL_GROUP)
-                                                     .group(&ADMIN_GROUP)
-    );
+pub async fn run() {
+    run_with(Http::new(config::DISCORD_TOKEN)).await
+}
 
-    match client.cache_and_http.http.get_current_user() {
+///Runs discord client over `http`, until it is shut down.
+async fn run_with(http: Http) {
+    let framework = serenity::framework::StandardFramework::new().help(&HELP)
+                                                                 .on_dispatch_error(on_dispatch_error)
+                                                                 .before(before_command)
+                                                                 .after(after_command)
+                                                                 .group(&GENERAL_GROUP)
+                                                                 .group(&ADMIN_GROUP);
+    framework.configure(configure());
+
+    let mut client = serenity::client::ClientBuilder::new_with_http(http, intents()).event_handler(Handler::new())
+                                                                                    .framework(framework)
+                                                                                    .await
+                                                                                    .expect("To create client");
+
+    match client.http.get_current_user().await {
         Ok(info) => {
-            SELF_ID.store(info.id.0, Ordering::Release);
+            SELF_ID.store(info.id.get(), Ordering::Release);
         },
         Err(error) => {
-            rogu::error!("Discord unable to get current user info: {}", error);
+            log::error!("Discord unable to get current user info: {}", error);
         }
     }
 
     if config::DISCORD.with_read(|config| config.owner) == 0 {
-        match client.cache_and_http.http.get_current_application_info() {
-            Ok(info) => {
-                rogu::info!("Discord setting new owner id={}", info.owner.id.0);
-                config::DISCORD.with_write(|config| config.owner = info.owner.id.0);
+        match client.http.get_current_application_info().await {
+            Ok(info) => match info.owner {
+                Some(owner) => {
+                    log::info!("Discord setting new owner id={}", owner.id);
+                    config::DISCORD.with_write(|config| config.owner = owner.id.get());
+                },
+                None => {
+                    log::error!("Discord application has no owner");
+                    STATS.increment(stats::DiscordNoAppInfo);
+                }
             },
             Err(error) => {
-                rogu::error!("Discord unable to get application information: {}", error);
+                log::error!("Discord unable to get application information: {}", error);
                 STATS.increment(stats::DiscordNoAppInfo);
             }
         };
     }
 
-    HTTP.write().replace(client.cache_and_http.clone());
-
-    while let Some((tweet_id, user_name, tweet_type)) = twitter::BUFFERED_TWEETS.dequeue() {
-        twitter::redirect_tweet(&client.cache_and_http.http, tweet_id, user_name, tweet_type);
-    }
+    set_http(Some(client.http.clone()));
+    SHARD_MANAGER.write().replace(client.shard_manager.clone());
 
     loop {
-        rogu::info!("Discord: start");
-        match client.start() {
+        log::info!("Discord: start");
+        match client.start().await {
             Ok(_) => {
                 STATS.increment(stats::DiscordShutdown);
                 break;
             }
             Err(error) => {
                 STATS.increment(stats::DiscordFailure);
-                rogu::warn!("Discord stopped with error: {}", error);
+                log::warn!("Discord stopped with error: {}", error);
             }
         }
     }
 
-    HTTP.write().take();
+    set_http(None);
+    SHARD_MANAGER.write().take();
+    health::set_discord_connected(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mock::{self, Mock};
+
+    const GUILD: u64 = 2000;
+    const WELCOME: u64 = 2001;
+    const GENERAL: u64 = 2002;
+    const TEAM_RINNE: u64 = 2010;
+    const TEAM_JEANNE: u64 = 2011;
+    const BOT_ROLE: u64 = 2012;
+
+    lazy_static::lazy_static! {
+        ///Discord client state is global, so only one client may be running at a time.
+        static ref LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+    }
+
+    fn guild() -> serde_json::Value {
+        let roles = vec![
+            mock::role(GUILD, "@everyone", 0, 0),
+            mock::role(TEAM_RINNE, "TeamRinne", 1, 0),
+            mock::role(TEAM_JEANNE, "TeamJeanne", 2, 0),
+            //Administrator, as replies check bot's permissions in cache
+            mock::role(BOT_ROLE, "Jeanne", 3, 8),
+        ];
+        let members = vec![
+            mock::member(GUILD, mock::user(mock::BOT, "Jeanne", true), &[BOT_ROLE]),
+            mock::member(GUILD, mock::user(mock::USER, "Kaito", false), &[TEAM_RINNE]),
+        ];
+
+        mock::guild(GUILD, roles, members, &[WELCOME, GENERAL, mock::FORBIDDEN])
+    }
+
+    fn command(id: u64, content: &str) -> serde_json::Value {
+        mock::message(id, GUILD, GENERAL, mock::user(mock::USER, "Kaito", false), content)
+    }
+
+    fn run_test<F: core::future::Future<Output = ()>>(test: impl FnOnce(Mock) -> F) {
+        let _lock = LOCK.lock();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("To create runtime");
+
+        rt.block_on(async move {
+            let mock = Mock::start(guild()).await;
+            let client = tokio::spawn(run_with(mock.http()));
+
+            test(mock).await;
+
+            let manager = SHARD_MANAGER.read().clone().expect("Discord to run");
+            manager.shutdown_all().await;
+            client.await.expect("To stop discord");
+        });
+    }
+
+    #[test]
+    fn should_greet_on_ready_and_welcome_new_member() {
+        config::DISCORD.with_write(|config| config.channels.welcome = WELCOME);
+
+        run_test(|mut mock| async move {
+            let greeting = mock.expect("POST", &format!("/channels/{}/messages", WELCOME)).await;
+            assert!(greeting.body.contains(constants::JEANNE_GREETING));
+
+            mock.dispatch("GUILD_MEMBER_ADD", mock::member(GUILD, mock::user(3000, "Bot", true), &[]));
+            mock.dispatch("GUILD_MEMBER_ADD", mock::member(GUILD, mock::user(3001, "Sena", false), &[]));
+
+            let welcome = mock.expect("POST", &format!("/channels/{}/messages", WELCOME)).await;
+            assert!(welcome.body.contains("please welcome <@3001>"), "Unexpected welcome {}", welcome.body);
+        });
+    }
+
+    #[test]
+    fn should_toggle_subscription() {
+        run_test(|mut mock| async move {
+            mock.dispatch("MESSAGE_CREATE", command(4000, "~subscribe naze"));
+            let reply = mock.expect("POST", &format!("/channels/{}/messages", GENERAL)).await;
+            assert!(reply.body.contains(constants::MSG_ADD_SUB));
+            assert!(config::DISCORD.with_read(|config| config.channels.naze.contains(&GENERAL)));
+
+            mock.dispatch("MESSAGE_CREATE", command(4001, "~subscribe naze"));
+            let reply = mock.expect("POST", &format!("/channels/{}/messages", GENERAL)).await;
+            assert!(reply.body.contains(constants::MSG_REMOVE_SUB));
+            assert!(!config::DISCORD.with_read(|config| config.channels.naze.contains(&GENERAL)));
+        });
+    }
+
+    #[test]
+    fn should_record_roll_only_once_it_is_shown() {
+        run_test(|mut mock| async move {
+            let history = |channel: u64| config::DISCORD.with_read(|config| config.rolls.get(&(mock::USER, channel)).map(|history| history.stats.rolls).unwrap_or(0));
+
+            mock.dispatch("MESSAGE_CREATE", mock::message(4003, GUILD, mock::FORBIDDEN, mock::user(mock::USER, "Kaito", false), "~dice 1d20"));
+            mock.expect("POST", &format!("/channels/{}/messages", mock::FORBIDDEN)).await;
+
+            mock.dispatch("MESSAGE_CREATE", command(4004, "~dice 1d20"));
+            mock.expect("POST", &format!("/channels/{}/messages", GENERAL)).await;
+
+            let recorded = async {
+                while history(GENERAL) == 0 {
+                    tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(core::time::Duration::from_secs(10), recorded).await.expect("To record roll");
+            assert_eq!(history(mock::FORBIDDEN), 0);
+        });
+    }
+
+    #[test]
+    fn should_set_waifu() {
+        run_test(|mut mock| async move {
+            //Roster is bound to Team roles once guild is created
+            let bound = async {
+                while config::DISCORD.with_read(|config| config.waifus(GUILD).iter().all(|waifu| waifu.role == 0)) {
+                    tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(core::time::Duration::from_secs(10), bound).await.expect("To bind waifu roles");
+
+            mock.dispatch("MESSAGE_CREATE", command(4002, "~set_waifu jeanne"));
+
+            mock.expect("DELETE", &format!("/guilds/{}/members/{}/roles/{}", GUILD, mock::USER, TEAM_RINNE)).await;
+            mock.expect("PUT", &format!("/guilds/{}/members/{}/roles/{}", GUILD, mock::USER, TEAM_JEANNE)).await;
+            let reply = mock.expect("POST", &format!("/channels/{}/messages", GENERAL)).await;
+            assert!(reply.body.contains("Set waifu of Kaito as Jeanne"), "Unexpected reply {}", reply.body);
+        });
+    }
 }
@@ -0,0 +1,134 @@
+use crate::{discord, constants};
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::fmt;
+use std::time;
+
+static DISCORD_CONNECTED: AtomicBool = AtomicBool::new(false);
+static TWITTER_LAST_MSG: AtomicU64 = AtomicU64::new(0);
+static CONFIG_SAVED: AtomicBool = AtomicBool::new(true);
+
+fn now() -> u64 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(now) => now.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+pub fn init() {
+    //Give twitter stream chance to start
+    TWITTER_LAST_MSG.store(now(), Ordering::Release);
+}
+
+///Sets whether discord gateway is connected.
+pub fn set_discord_connected(is_connected: bool) {
+    DISCORD_CONNECTED.store(is_connected, Ordering::Release);
+}
+
+///Marks that twitter stream is alive at this moment.
+pub fn twitter_message() {
+    TWITTER_LAST_MSG.store(now(), Ordering::Release);
+}
+
+///Sets outcome of last attempt to save configuration.
+pub fn set_config_saved(is_saved: bool) {
+    CONFIG_SAVED.store(is_saved, Ordering::Release);
+}
+
+///Snapshot of bot's health
+pub struct Report {
+    ///Gateway connection state as reported by `ready`/`resume`/stage events.
+    pub discord_connected: bool,
+    ///Number of connected shards.
+    pub discord_shards_connected: usize,
+    ///Number of shards known to shard manager.
+    pub discord_shards: usize,
+    ///Seconds since last message in twitter stream.
+    pub twitter_silence: u64,
+    ///Whether last config save succeeded.
+    pub config_saved: bool,
+}
+
+impl Report {
+    pub async fn collect() -> Self {
+        let (discord_shards_connected, discord_shards) = discord::shards_status().await;
+
+        Self {
+            discord_connected: DISCORD_CONNECTED.load(Ordering::Acquire),
+            discord_shards_connected,
+            discord_shards,
+            twitter_silence: now().saturating_sub(TWITTER_LAST_MSG.load(Ordering::Acquire)),
+            config_saved: CONFIG_SAVED.load(Ordering::Acquire),
+        }
+    }
+
+    #[inline]
+    fn is_twitter_alive(&self) -> bool {
+        self.twitter_silence <= constants::TWITTER_STREAM_TIMEOUT.as_secs()
+    }
+
+    #[inline]
+    fn is_discord_ready(&self) -> bool {
+        self.discord_connected && self.discord_shards > 0 && self.discord_shards_connected == self.discord_shards
+    }
+
+    ///Returns whether bot is alive.
+    ///
+    ///Discord re-connects on its own, so only failures that cannot be recovered without restart
+    ///are considered.
+    pub fn is_alive(&self) -> bool {
+        self.is_twitter_alive()
+    }
+
+    ///Returns whether bot is fully operational.
+    ///
+    ///Failure to save config may be transient, so it only affects readiness.
+    pub fn is_ready(&self) -> bool {
+        self.is_alive() && self.config_saved && self.is_discord_ready()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "discord_connected: {}", self.discord_connected)?;
+        writeln!(f, "discord_shards:    {}/{}", self.discord_shards_connected, self.discord_shards)?;
+        writeln!(f, "twitter_silence:   {}s", self.twitter_silence)?;
+        writeln!(f, "config_saved:      {}", self.config_saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        Report {
+            discord_connected: true,
+            discord_shards_connected: 1,
+            discord_shards: 1,
+            twitter_silence: 0,
+            config_saved: true,
+        }
+    }
+
+    #[test]
+    fn should_stay_alive_on_config_save_failure() {
+        let mut report = report();
+        assert!(report.is_alive());
+        assert!(report.is_ready());
+
+        report.config_saved = false;
+        assert!(report.is_alive());
+        assert!(!report.is_ready());
+
+        report.config_saved = true;
+        report.twitter_silence = constants::TWITTER_STREAM_TIMEOUT.as_secs() + 1;
+        assert!(!report.is_alive());
+        assert!(!report.is_ready());
+
+        report.twitter_silence = 0;
+        report.discord_shards_connected = 0;
+        assert!(report.is_alive());
+        assert!(!report.is_ready());
+    }
+}
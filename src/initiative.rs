@@ -0,0 +1,388 @@
+use serde::{Serialize, Deserialize};
+
+use core::fmt;
+use std::collections::BTreeSet;
+
+///Maximum number of combatants per tracker
+const COMBATANTS_LIMIT: usize = 30;
+///Maximum length of combatant's name
+const NAME_MAX_LEN: usize = 32;
+///Maximum number of conditions per combatant
+const CONDITIONS_LIMIT: usize = 8;
+///Maximum length of condition
+const CONDITION_MAX_LEN: usize = 24;
+
+#[derive(PartialEq, Eq, Debug)]
+///Possible errors when modifying tracker
+pub enum Error {
+    ///Name is empty, too long or contains whitespace
+    InvalidName,
+    ///Combatant with such name already exists
+    Duplicate,
+    ///Tracker has `COMBATANTS_LIMIT` combatants
+    TooManyCombatants,
+    ///No combatant with such name
+    Unknown,
+    ///Nobody to take turns
+    Empty,
+    ///Combatant has no HP to change
+    NoHp,
+    ///Condition is empty or too long
+    InvalidCondition,
+    ///Combatant has `CONDITIONS_LIMIT` conditions
+    TooManyConditions,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidName => write!(f, "Name should be single word of no more than {} characters", NAME_MAX_LEN),
+            Error::Duplicate => f.write_str("Combatant with such name is already in initiative"),
+            Error::TooManyCombatants => write!(f, "Too many combatants, no more than {} is allowed", COMBATANTS_LIMIT),
+            Error::Unknown => f.write_str("There is no such combatant"),
+            Error::Empty => f.write_str("There are no combatants"),
+            Error::NoHp => f.write_str("Combatant has no HP set"),
+            Error::InvalidCondition => write!(f, "Condition should be no more than {} characters", CONDITION_MAX_LEN),
+            Error::TooManyConditions => write!(f, "Too many conditions, no more than {} is allowed", CONDITIONS_LIMIT),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+///Change of HP
+pub enum Hp {
+    Set(i32),
+    Add(i32),
+}
+
+impl Hp {
+    ///Parses `+n`, `-n` as relative change and `n` as new value
+    pub fn from_str(text: &str) -> Option<Self> {
+        let text = text.trim();
+
+        match text.starts_with(&['+', '-'][..]) {
+            true => text.parse().ok().map(Hp::Add),
+            false => text.parse().ok().map(Hp::Set),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Combatant {
+    pub name: String,
+    ///Initiative modifier
+    pub modifier: i16,
+    ///Initiative, `None` until rolled
+    pub initiative: Option<i32>,
+    ///Current and maximum HP, if tracked
+    pub hp: Option<(i32, i32)>,
+    pub conditions: BTreeSet<String>,
+}
+
+impl fmt::Display for Combatant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.initiative {
+            Some(initiative) => write!(f, "{:>3}", initiative)?,
+            None => f.write_str("  -")?,
+        }
+
+        write!(f, " {} ({:+})", self.name, self.modifier)?;
+
+        if let Some((hp, max)) = self.hp {
+            write!(f, " HP {}/{}", hp, max)?;
+        }
+
+        if !self.conditions.is_empty() {
+            f.write_str(" [")?;
+            for (idx, condition) in self.conditions.iter().enumerate() {
+                if idx > 0 {
+                    f.write_str(", ")?;
+                }
+                f.write_str(condition)?;
+            }
+            f.write_str("]")?;
+        }
+
+        Ok(())
+    }
+}
+
+///Initiative order of single channel
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Tracker {
+    ///Combatants in initiative order, ones without initiative last
+    pub combatants: Vec<Combatant>,
+    ///Index of combatant, whose turn it is
+    pub turn: usize,
+    ///Current round, `0` until combat starts
+    pub round: u32,
+}
+
+impl Tracker {
+    fn find(&self, name: &str) -> Result<usize, Error> {
+        self.combatants.iter().position(|combatant| combatant.name.eq_ignore_ascii_case(name)).ok_or(Error::Unknown)
+    }
+
+    fn find_mut(&mut self, name: &str) -> Result<&mut Combatant, Error> {
+        let idx = self.find(name)?;
+        Ok(&mut self.combatants[idx])
+    }
+
+    ///Sorts by initiative, then by modifier, keeping current turn.
+    fn sort(&mut self) {
+        let current = self.combatants.get(self.turn).map(|combatant| combatant.name.clone());
+
+        //Stable sort keeps order of addition for ties
+        self.combatants.sort_by_key(|combatant| core::cmp::Reverse((combatant.initiative, combatant.modifier)));
+
+        self.turn = match current {
+            Some(current) => self.find(&current).unwrap_or(0),
+            None => 0,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.combatants.is_empty()
+    }
+
+    ///Adds combatant without initiative.
+    pub fn add(&mut self, name: &str, modifier: i16, hp: Option<i32>) -> Result<(), Error> {
+        if name.is_empty() || name.len() > NAME_MAX_LEN || name.contains(char::is_whitespace) {
+            return Err(Error::InvalidName);
+        } else if self.find(name).is_ok() {
+            return Err(Error::Duplicate);
+        } else if self.combatants.len() >= COMBATANTS_LIMIT {
+            return Err(Error::TooManyCombatants);
+        }
+
+        self.combatants.push(Combatant {
+            name: name.to_owned(),
+            modifier,
+            initiative: None,
+            hp: hp.map(|hp| (hp, hp)),
+            conditions: BTreeSet::new(),
+        });
+        self.sort();
+
+        Ok(())
+    }
+
+    ///Removes combatant, passing turn to the next one if it was theirs.
+    pub fn remove(&mut self, name: &str) -> Result<Combatant, Error> {
+        let idx = self.find(name)?;
+        let combatant = self.combatants.remove(idx);
+
+        if idx < self.turn {
+            self.turn -= 1;
+        }
+        if self.turn >= self.combatants.len() {
+            self.turn = 0;
+        }
+
+        Ok(combatant)
+    }
+
+    ///Rolls initiative of every combatant in current order, starting first round.
+    ///
+    ///`d20` is called once per combatant.
+    pub fn roll<R: FnMut() -> u16>(&mut self, mut d20: R) -> Result<(), Error> {
+        if self.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        for combatant in self.combatants.iter_mut() {
+            combatant.initiative = Some(d20() as i32 + combatant.modifier as i32);
+        }
+
+        self.sort();
+        self.turn = 0;
+        self.round = 1;
+
+        Ok(())
+    }
+
+    ///Sets initiative of combatant manually.
+    pub fn set(&mut self, name: &str, initiative: i32) -> Result<(), Error> {
+        self.find_mut(name)?.initiative = Some(initiative);
+        self.sort();
+
+        Ok(())
+    }
+
+    ///Passes turn to the next combatant, returning them.
+    pub fn next(&mut self) -> Result<&Combatant, Error> {
+        if self.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        match self.round {
+            0 => {
+                self.turn = 0;
+                self.round = 1;
+            },
+            _ => {
+                self.turn += 1;
+                if self.turn >= self.combatants.len() {
+                    self.turn = 0;
+                    self.round += 1;
+                }
+            },
+        }
+
+        Ok(&self.combatants[self.turn])
+    }
+
+    ///Changes HP of combatant, returning new HP.
+    ///
+    ///Setting HP of combatant without HP makes it their maximum.
+    pub fn hp(&mut self, name: &str, change: Hp) -> Result<(i32, i32), Error> {
+        let combatant = self.find_mut(name)?;
+
+        let hp = match (combatant.hp, change) {
+            (None, Hp::Set(hp)) => (hp, hp),
+            (None, Hp::Add(_)) => return Err(Error::NoHp),
+            (Some((_, max)), Hp::Set(hp)) => (hp, max),
+            (Some((hp, max)), Hp::Add(change)) => (hp.saturating_add(change), max),
+        };
+
+        combatant.hp = Some(hp);
+        Ok(hp)
+    }
+
+    ///Adds or removes condition, returning whether combatant has it now.
+    pub fn toggle_condition(&mut self, name: &str, condition: &str) -> Result<bool, Error> {
+        let condition = condition.trim().to_lowercase();
+        if condition.is_empty() || condition.chars().count() > CONDITION_MAX_LEN {
+            return Err(Error::InvalidCondition);
+        }
+
+        let conditions = &mut self.find_mut(name)?.conditions;
+        match conditions.remove(&condition) {
+            true => Ok(false),
+            false => match conditions.len() >= CONDITIONS_LIMIT {
+                true => Err(Error::TooManyConditions),
+                false => Ok(conditions.insert(condition)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Tracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.round {
+            0 => writeln!(f, "Combat is not started")?,
+            round => writeln!(f, "Round {}", round)?,
+        }
+
+        for (idx, combatant) in self.combatants.iter().enumerate() {
+            let marker = match self.round > 0 && idx == self.turn {
+                true => '>',
+                false => ' ',
+            };
+
+            writeln!(f, "{} {}", marker, combatant)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> Tracker {
+        let mut tracker = Tracker::default();
+        tracker.add("Goblin", 2, Some(7)).expect("To add goblin");
+        tracker.add("Fighter", 1, None).expect("To add fighter");
+        tracker.add("Wizard", 3, Some(12)).expect("To add wizard");
+        tracker
+    }
+
+    fn order(tracker: &Tracker) -> Vec<&str> {
+        tracker.combatants.iter().map(|combatant| combatant.name.as_str()).collect()
+    }
+
+    #[test]
+    fn should_validate_combatants() {
+        let mut tracker = tracker();
+
+        assert_eq!(tracker.add("goblin", 0, None), Err(Error::Duplicate));
+        assert_eq!(tracker.add("Two words", 0, None), Err(Error::InvalidName));
+        assert_eq!(tracker.add("", 0, None), Err(Error::InvalidName));
+        assert_eq!(tracker.remove("Orc").err(), Some(Error::Unknown));
+
+        for idx in tracker.combatants.len()..COMBATANTS_LIMIT {
+            tracker.add(&format!("Orc{}", idx), 0, None).expect("To add orc");
+        }
+        assert_eq!(tracker.add("Dragon", 0, None), Err(Error::TooManyCombatants));
+        assert_eq!(Tracker::default().next().err(), Some(Error::Empty));
+    }
+
+    #[test]
+    fn should_roll_and_sort() {
+        let mut tracker = tracker();
+        //Until rolled, combatants are ordered by modifier
+        assert_eq!(order(&tracker), ["Wizard", "Goblin", "Fighter"]);
+
+        let mut rolls = [10u16, 15, 9].iter();
+        tracker.roll(|| *rolls.next().expect("To roll per combatant")).expect("To roll");
+
+        assert_eq!(order(&tracker), ["Goblin", "Wizard", "Fighter"]);
+        assert_eq!(tracker.combatants[0].initiative, Some(17));
+        assert_eq!((tracker.turn, tracker.round), (0, 1));
+
+        //Ties are broken by modifier and turn stays with goblin
+        tracker.set("goblin", 13).expect("To set initiative");
+        assert_eq!(order(&tracker), ["Wizard", "Goblin", "Fighter"]);
+        assert_eq!(tracker.turn, 1);
+        tracker.set("fighter", 20).expect("To set initiative");
+        assert_eq!(order(&tracker), ["Fighter", "Wizard", "Goblin"]);
+        assert_eq!(tracker.turn, 2);
+
+        tracker.add("Rogue", 4, None).expect("To add rogue");
+        assert_eq!(order(&tracker), ["Fighter", "Wizard", "Goblin", "Rogue"]);
+    }
+
+    #[test]
+    fn should_advance_turns() {
+        let mut tracker = tracker();
+        let mut rolls = [10u16, 15, 9].iter();
+        tracker.roll(|| *rolls.next().expect("To roll per combatant")).expect("To roll");
+
+        assert_eq!(tracker.next().map(|combatant| combatant.name.as_str()), Ok("Wizard"));
+        assert_eq!(tracker.next().map(|combatant| combatant.name.as_str()), Ok("Fighter"));
+        assert_eq!(tracker.round, 1);
+        assert_eq!(tracker.next().map(|combatant| combatant.name.as_str()), Ok("Goblin"));
+        assert_eq!(tracker.round, 2);
+
+        tracker.next().expect("To pass turn");
+        tracker.remove("Wizard").expect("To remove wizard");
+        assert_eq!(tracker.combatants[tracker.turn].name, "Fighter");
+        tracker.remove("Goblin").expect("To remove goblin");
+        assert_eq!((tracker.turn, tracker.combatants[tracker.turn].name.as_str()), (0, "Fighter"));
+        assert!(tracker.to_string().starts_with("Round 2\n> "));
+    }
+
+    #[test]
+    fn should_track_hp_and_conditions() {
+        let mut tracker = tracker();
+
+        assert_eq!(Hp::from_str("-5"), Some(Hp::Add(-5)));
+        assert_eq!(Hp::from_str("+3"), Some(Hp::Add(3)));
+        assert_eq!(Hp::from_str("10"), Some(Hp::Set(10)));
+        assert_eq!(Hp::from_str("ten"), None);
+
+        assert_eq!(tracker.hp("goblin", Hp::Add(-5)), Ok((2, 7)));
+        assert_eq!(tracker.hp("goblin", Hp::Add(-5)), Ok((-3, 7)));
+        assert_eq!(tracker.hp("goblin", Hp::Set(7)), Ok((7, 7)));
+        assert_eq!(tracker.hp("fighter", Hp::Add(-1)), Err(Error::NoHp));
+        assert_eq!(tracker.hp("fighter", Hp::Set(20)), Ok((20, 20)));
+
+        assert_eq!(tracker.toggle_condition("wizard", "Prone"), Ok(true));
+        assert_eq!(tracker.toggle_condition("wizard", "concentrating"), Ok(true));
+        assert_eq!(tracker.toggle_condition("wizard", " "), Err(Error::InvalidCondition));
+        assert_eq!(tracker.to_string(), "Combat is not started\n    - Wizard (+3) HP 12/12 [concentrating, prone]\n    - Goblin (+2) HP 7/7\n    - Fighter (+1) HP 20/20\n");
+        assert_eq!(tracker.toggle_condition("wizard", "prone"), Ok(false));
+    }
+}
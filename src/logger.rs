@@ -0,0 +1,327 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::{env, fs, time};
+use core::fmt;
+
+use serde::Serialize;
+
+use crate::report;
+
+const DEFAULT_FILTER: &str = "warn,jeanne=info";
+const LOG_FILE_NAME: &str = "jeanne.log";
+const LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const LOG_FILE_MAX_NUM: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+///Log level filter with per module overrides.
+///
+///Spec is comma separated list of `level` and `module=level`, i.e. `warn,jeanne::twitter=debug`
+#[derive(Clone)]
+pub struct Filter {
+    default: log::LevelFilter,
+    modules: Vec<(String, log::LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut res = Self {
+            default: log::LevelFilter::Info,
+            modules: Vec::new(),
+        };
+
+        res.apply(spec)?;
+        Ok(res)
+    }
+
+    ///Applies spec on top of existing filter.
+    ///
+    ///Filter is left unchanged on error.
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let mut res = self.clone();
+
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let mut split = part.splitn(2, '=');
+            let (module, level) = match (split.next(), split.next()) {
+                (Some(level), None) => (None, level),
+                (Some(module), Some(level)) => (Some(module.trim()), level),
+                _ => unreach!(),
+            };
+
+            let level = match level.trim().parse::<log::LevelFilter>() {
+                Ok(level) => level,
+                Err(_) => return Err(format!("Invalid level '{}'", level.trim())),
+            };
+
+            match module {
+                Some(module) => res.set(module, level),
+                None => res.default = level,
+            }
+        }
+
+        *self = res;
+        Ok(())
+    }
+
+    ///Sets level for module, overriding existing one.
+    pub fn set(&mut self, module: &str, level: log::LevelFilter) {
+        match self.modules.iter_mut().find(|(name, _)| name == module) {
+            Some(existing) => existing.1 = level,
+            None => self.modules.push((module.to_owned(), level)),
+        }
+        //Longest prefix must match first
+        self.modules.sort_unstable_by_key(|(name, _)| core::cmp::Reverse(name.len()));
+    }
+
+    fn level(&self, target: &str) -> log::LevelFilter {
+        for (module, level) in self.modules.iter() {
+            if target.starts_with(module.as_str()) && (target.len() == module.len() || target[module.len()..].starts_with("::")) {
+                return *level;
+            }
+        }
+
+        self.default
+    }
+
+    fn max_level(&self) -> log::LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, core::cmp::max)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default)?;
+        for (module, level) in self.modules.iter().rev() {
+            write!(f, ",{}={}", module, level)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, idx: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", idx));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for idx in (1..LOG_FILE_MAX_NUM).rev() {
+            let from = match idx {
+                1 => self.path.clone(),
+                idx => self.rotated_path(idx - 1),
+            };
+
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(idx))?;
+            }
+        }
+
+        self.file = fs::OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size + line.len() as u64 > LOG_FILE_MAX_SIZE {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: &'a str,
+    level: &'a str,
+    target: &'a str,
+    location: String,
+    msg: String,
+}
+
+struct Logger {
+    filter: parking_lot::RwLock<Filter>,
+    format: Format,
+    file: parking_lot::Mutex<Option<RotatingFile>>,
+}
+
+impl Logger {
+    fn format(&self, record: &log::Record) -> String {
+        let time = Timestamp::now().to_string();
+        let location = format!("{}:{}", record.file().unwrap_or("<unknown>"), record.line().unwrap_or(0));
+
+        match self.format {
+            Format::Text => format!("{} [{:<5}] - [{}] - {}\n", time, record.level(), location, record.args()),
+            Format::Json => {
+                let record = JsonRecord {
+                    time: &time,
+                    level: record.level().as_str(),
+                    target: record.target(),
+                    location,
+                    msg: record.args().to_string(),
+                };
+
+                match serde_json::to_string(&record) {
+                    Ok(mut line) => {
+                        line.push('\n');
+                        line
+                    },
+                    Err(_) => String::new(),
+                }
+            }
+        }
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.filter.read().level(metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(record);
+
+        match record.level() {
+            log::Level::Error | log::Level::Warn => {
+                let _ = io::stderr().write_all(line.as_bytes());
+            },
+            _ => {
+                let _ = io::stdout().write_all(line.as_bytes());
+            }
+        }
+
+        if let Some(file) = self.file.lock().as_mut() {
+            let _ = file.write(line.as_bytes());
+        }
+
+        //Report own errors only, avoiding loop on failure to send report.
+        if record.level() <= log::Level::Warn && record.target().starts_with("jeanne") && !record.target().starts_with("jeanne::report") {
+            report::push(record.level(), record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        if let Some(file) = self.file.lock().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LOGGER: Logger = {
+        let filter = env::var("JEANNE_LOG").ok()
+                                          .and_then(|spec| Filter::parse(&spec).map_err(|error| eprintln!("JEANNE_LOG is invalid: {}", error)).ok())
+                                          .unwrap_or_else(|| Filter::parse(DEFAULT_FILTER).expect("Valid default filter"));
+
+        let format = match env::var("JEANNE_LOG_FORMAT") {
+            Ok(ref format) if format.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Text,
+        };
+
+        let file = match RotatingFile::open(log_path()) {
+            Ok(file) => Some(file),
+            Err(error) => {
+                eprintln!("Unable to open log file: {}", error);
+                None
+            }
+        };
+
+        Logger {
+            filter: parking_lot::RwLock::new(filter),
+            format,
+            file: parking_lot::Mutex::new(file),
+        }
+    };
+}
+
+fn log_path() -> PathBuf {
+    match env::current_exe() {
+        Ok(mut result) => {
+            result.set_file_name(LOG_FILE_NAME);
+            result
+        },
+        Err(_) => PathBuf::from(LOG_FILE_NAME),
+    }
+}
+
+///Initializes logger using `JEANNE_LOG` filter spec and `JEANNE_LOG_FORMAT`
+pub fn init() {
+    log::set_max_level(LOGGER.filter.read().max_level());
+    if log::set_logger(&*LOGGER).is_err() {
+        eprintln!("Logger is already set");
+    }
+}
+
+///Returns current filter spec.
+pub fn filter() -> String {
+    LOGGER.filter.read().to_string()
+}
+
+///Applies spec on top of current filter.
+pub fn update_filter(spec: &str) -> Result<String, String> {
+    let mut filter = LOGGER.filter.write();
+    filter.apply(spec)?;
+    log::set_max_level(filter.max_level());
+
+    Ok(filter.to_string())
+}
+
+///UTC time in ISO 8601 format
+struct Timestamp(time::Duration);
+
+impl Timestamp {
+    fn now() -> Self {
+        Self(time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default())
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let days = (secs / 86400) as i64;
+        let day_secs = secs % 86400;
+
+        //Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, day_secs / 3600, day_secs % 3600 / 60, day_secs % 60, self.0.subsec_millis())
+    }
+}
@@ -1,32 +1,42 @@
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::style))]
 
-use std::thread;
-
 #[macro_use]
 mod utils;
+mod logger;
 mod rt;
+mod rng;
 mod constants;
+mod dice;
+mod initiative;
 mod stats;
+mod health;
+mod server;
+mod report;
 mod config;
 mod discord;
 mod twitter;
 
 fn main() {
+    logger::init();
     rt::init();
     config::init();
+    rng::init();
+    health::init();
 
-    thread::Builder::new().name("twitter-worker".to_owned())
-                          .spawn(twitter::worker)
-                          .expect("To create twitter thread");
+    rt::spawn(server::run());
+    rt::spawn(config::run());
+    rt::spawn(report::run());
+    rt::spawn(rng::run());
+    rt::spawn(twitter::worker(twitter::Api::default()));
 
-    discord::run();
+    rt::block_on(discord::run());
 
     match crate::config::DISCORD.save() {
         Ok(_) => {
-            rogu::info!("Discord config is updated.");
+            log::info!("Discord config is updated.");
         },
         Err(error) => {
-            rogu::error!("Discord unable to save config: {}", error);
+            log::error!("Discord unable to save config: {}", error);
         }
     }
 }
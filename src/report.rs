@@ -0,0 +1,119 @@
+use crate::{config, constants, discord};
+use crate::utils::mpmc::Q64;
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use std::time::Instant;
+
+//Discord limits message to 2000 characters
+const REPORT_MAX_LEN: usize = 1900;
+
+static PENDING: Q64<(log::Level, String)> = Q64::new();
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+///Queues log record to be reported.
+///
+///Records are dropped when queue is full.
+pub fn push(level: log::Level, msg: String) {
+    if PENDING.enqueue((level, msg)).is_err() {
+        DROPPED.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+struct Reporter {
+    //Time when message was last reported
+    reported: HashMap<String, Instant>,
+}
+
+impl Reporter {
+    fn new() -> Self {
+        Self {
+            reported: HashMap::new(),
+        }
+    }
+
+    fn collect(&mut self, first: (log::Level, String)) -> Option<String> {
+        let now = Instant::now();
+        self.reported.retain(|_, reported| now.duration_since(*reported) < constants::REPORT_DEDUP_WINDOW);
+
+        let mut pending = Vec::<(log::Level, String, usize)>::new();
+        for (level, msg) in core::iter::once(first).chain(PENDING.try_iter()) {
+            if self.reported.contains_key(&msg) {
+                continue;
+            }
+
+            match pending.iter_mut().find(|(_, pending, _)| *pending == msg) {
+                Some(existing) => existing.2 += 1,
+                None => pending.push((level, msg, 1)),
+            }
+        }
+
+        let dropped = DROPPED.swap(0, Ordering::AcqRel);
+
+        if pending.is_empty() && dropped == 0 {
+            return None;
+        }
+
+        let mut report = String::new();
+        let mut skipped = 0;
+        for (level, msg, count) in pending {
+            let len = report.len();
+            let _ = match count {
+                1 => writeln!(report, "**{}** {}", level, msg),
+                count => writeln!(report, "**{}** {} (x{})", level, msg, count),
+            };
+
+            if report.len() > REPORT_MAX_LEN {
+                report.truncate(len);
+                skipped += 1;
+            } else {
+                self.reported.insert(msg, now);
+            }
+        }
+
+        if skipped > 0 || dropped > 0 {
+            let _ = write!(report, "...and {} more", skipped + dropped);
+        }
+
+        Some(report)
+    }
+}
+
+async fn send(http: &serenity::http::Http, report: &str) {
+    let (channel, owner) = config::DISCORD.with_read(|config| (config.reports, config.owner));
+
+    let channel = match channel {
+        0 => match owner {
+            0 => return,
+            owner => match serenity::model::id::UserId::new(owner).create_dm_channel(http).await {
+                Ok(channel) => channel.id,
+                Err(error) => {
+                    log::warn!("Unable to open DM with owner: {}", error);
+                    return;
+                }
+            }
+        },
+        channel => serenity::model::id::ChannelId::new(channel),
+    };
+
+    if let Err(error) = discord::say(http, channel, report).await {
+        log::warn!("Unable to send error report: {}", error);
+    }
+}
+
+///Forwards queued errors to discord, at most once per `REPORT_INTERVAL`.
+pub async fn run() {
+    let mut reporter = Reporter::new();
+
+    loop {
+        let first = PENDING.dequeue_async().await;
+        //Let errors accumulate, limiting rate of reports
+        tokio::time::sleep(constants::REPORT_INTERVAL).await;
+        let http = discord::wait_http().await;
+
+        if let Some(report) = reporter.collect(first) {
+            send(&http, &report).await;
+        }
+    }
+}
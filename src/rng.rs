@@ -0,0 +1,391 @@
+use crate::{constants, config};
+
+use core::fmt;
+use std::collections::VecDeque;
+
+use ring::rand::SecureRandom;
+use serde::{Serialize, Deserialize};
+
+const SEED_LEN: usize = 32;
+///Number of revealed seeds to remember
+const REVEALED_LEN: usize = 16;
+
+pub type Seed = [u8; SEED_LEN];
+
+///Lower case hex representation of bytes
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(now) => now.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+fn entropy() -> Seed {
+    let mut seed = [0u8; SEED_LEN];
+    ring::rand::SystemRandom::new().fill(&mut seed).expect("To get OS entropy");
+    seed
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut res = [0u8; 32];
+    res.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    res
+}
+
+///Wyhash based PRNG, the same as used by `cute_dnd_dice`
+pub struct Random(u64);
+
+impl Random {
+    ///Creates generator for roll `nonce` of epoch with `seed`
+    fn new(seed: &Seed, nonce: u64) -> Self {
+        let mut data = [0u8; SEED_LEN + 8];
+        data[..SEED_LEN].copy_from_slice(seed);
+        data[SEED_LEN..].copy_from_slice(&nonce.to_le_bytes());
+
+        let hash = sha256(&data);
+        let mut state = [0u8; 8];
+        state.copy_from_slice(&hash[..8]);
+
+        Self(u64::from_le_bytes(state))
+    }
+
+    pub fn gen(&mut self) -> u64 {
+        const SEED_MOD: u64 = 0xa0761d6478bd642f;
+        const SEED_EXTRA: u64 = 0xe7037ed1a0b428db;
+
+        self.0 = self.0.wrapping_add(SEED_MOD);
+        let seeds = u128::from(self.0) * u128::from(self.0 ^ SEED_EXTRA);
+        (seeds as u64) ^ ((seeds >> 64) as u64)
+    }
+}
+
+///Identifies roll, which can be verified once seed of its epoch is revealed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ticket {
+    pub epoch: u64,
+    pub nonce: u64,
+}
+
+impl Ticket {
+    ///Parses ticket in format `<epoch>.<nonce>`
+    pub fn from_str(text: &str) -> Option<Self> {
+        let mut split = text.trim().trim_start_matches('#').splitn(2, '.');
+
+        match (split.next().map(str::parse), split.next().map(str::parse)) {
+            (Some(Ok(epoch)), Some(Ok(nonce))) => Some(Self {
+                epoch,
+                nonce,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}.{}", self.epoch, self.nonce)
+    }
+}
+
+///Seeds to keep across restarts
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Seeds {
+    ///Epoch and seed in use, revealed on next start
+    pub current: Option<(u64, Seed)>,
+    ///Seeds of previous epochs, newest last
+    pub revealed: VecDeque<(u64, Seed)>,
+}
+
+#[derive(Clone)]
+struct Epoch {
+    ///Unix timestamp of epoch's start, keeping ids unique across restarts
+    id: u64,
+    seed: Seed,
+    nonce: u64,
+}
+
+#[derive(Clone)]
+struct State {
+    current: Epoch,
+    ///Seeds of previous epochs, newest last
+    revealed: VecDeque<(u64, Seed)>,
+}
+
+impl State {
+    fn reseed(&mut self) {
+        let id = core::cmp::max(unix_time(), self.current.id + 1);
+        let old = core::mem::replace(&mut self.current, Epoch {
+            id,
+            seed: entropy(),
+            nonce: 0,
+        });
+
+        if self.revealed.len() >= REVEALED_LEN {
+            self.revealed.pop_front();
+        }
+        self.revealed.push_back((old.id, old.seed));
+    }
+
+    ///Restores seeds of previous run, revealing seed that was in use.
+    fn restore(&mut self, seeds: Seeds) {
+        self.revealed = seeds.revealed;
+        if let Some((id, seed)) = seeds.current {
+            self.current = Epoch {
+                id,
+                seed,
+                nonce: 0,
+            };
+            self.reseed();
+        }
+    }
+
+    fn seeds(&self) -> Seeds {
+        Seeds {
+            current: Some((self.current.id, self.current.seed)),
+            revealed: self.revealed.clone(),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: parking_lot::Mutex<State> = parking_lot::Mutex::new(State {
+        current: Epoch {
+            id: unix_time(),
+            seed: entropy(),
+            nonce: 0,
+        },
+        revealed: VecDeque::new(),
+    });
+}
+
+fn seed_dice() {
+    let mut seed = [0u8; 8];
+    seed.copy_from_slice(&entropy()[..8]);
+    cute_dnd_dice::set_seed(u64::from_le_bytes(seed));
+}
+
+///Saves seeds to disk, so that rolls can be verified after restart.
+fn save(seeds: Seeds) {
+    config::DISCORD.with_write(|config| config.seeds = seeds);
+    config::DISCORD.flush();
+}
+
+///Seeds random sources from OS entropy, revealing seed of previous run.
+///
+///Must be called after config is loaded and before any roll.
+pub fn init() {
+    seed_dice();
+    let seeds = config::DISCORD.with_read(|config| config.seeds.clone());
+
+    let mut state = STATE.lock();
+    state.restore(seeds);
+    let seeds = state.seeds();
+    drop(state);
+
+    save(seeds);
+}
+
+///Starts new epoch, revealing seed of current one.
+///
+///New seeds are passed to `persist` before any ticket of new epoch is given.
+fn reseed_with<P: FnOnce(Seeds)>(persist: P) {
+    seed_dice();
+
+    //Current epoch is still used, while new one is persisted
+    let mut next = STATE.lock().clone();
+    next.reseed();
+    persist(next.seeds());
+
+    *STATE.lock() = next;
+}
+
+///Reseeds every `RESEED_INTERVAL`
+pub async fn run() {
+    loop {
+        tokio::time::sleep(constants::RESEED_INTERVAL).await;
+        reseed_with(save);
+        log::info!("Dice are reseeded");
+    }
+}
+
+///Returns generator for new committed roll.
+pub fn commit() -> (Ticket, Random) {
+    let mut state = STATE.lock();
+    let epoch = &mut state.current;
+
+    let ticket = Ticket {
+        epoch: epoch.id,
+        nonce: epoch.nonce,
+    };
+    epoch.nonce += 1;
+
+    (ticket, Random::new(&epoch.seed, ticket.nonce))
+}
+
+///Returns current epoch and commitment to its seed, that is SHA-256 hash of seed.
+pub fn commitment() -> (u64, [u8; 32]) {
+    let state = STATE.lock();
+    (state.current.id, sha256(&state.current.seed))
+}
+
+///Returns recently revealed seeds, newest first.
+pub fn revealed() -> Vec<(u64, Seed)> {
+    STATE.lock().revealed.iter().rev().cloned().collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    ///Seed is not revealed yet
+    NotRevealed,
+    ///Seed of epoch is no longer remembered
+    Unknown,
+}
+
+///Returns generator, that was used for roll with `ticket`, if its seed is revealed.
+pub fn replay(ticket: Ticket) -> Result<Random, VerifyError> {
+    let state = STATE.lock();
+
+    if ticket.epoch >= state.current.id {
+        return Err(VerifyError::NotRevealed);
+    }
+
+    match state.revealed.iter().find(|(id, _)| *id == ticket.epoch) {
+        Some((_, seed)) => Ok(Random::new(seed, ticket.nonce)),
+        None => Err(VerifyError::Unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reseed() {
+        reseed_with(|_| ())
+    }
+
+    fn parse_hex(text: &str) -> Option<Seed> {
+        let mut res = [0u8; SEED_LEN];
+
+        if text.len() != SEED_LEN * 2 || !text.is_ascii() {
+            return None;
+        }
+
+        for (idx, byte) in res.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[idx * 2..idx * 2 + 2], 16).ok()?;
+        }
+
+        Some(res)
+    }
+
+    ///Replays roll following documented scheme, as players would do.
+    fn replay_with_seed(seed: &str, nonce: u64) -> Option<([u8; 32], Random)> {
+        parse_hex(seed).map(|seed| (sha256(&seed), Random::new(&seed, nonce)))
+    }
+
+    #[test]
+    fn should_persist_new_seed_before_using_it() {
+        let (epoch, commitment) = commitment();
+        let mut is_persisted = false;
+
+        reseed_with(|seeds| {
+            let (_, seed) = seeds.current.expect("To have new epoch");
+            assert!(seeds.revealed.iter().any(|(id, old)| *id == epoch && sha256(old) == commitment));
+            //New seed is not used, until it is persisted
+            assert_ne!(super::commitment().1, sha256(&seed));
+            is_persisted = true;
+        });
+
+        assert!(is_persisted);
+    }
+
+    #[test]
+    fn should_replay_committed_roll_after_reveal() {
+        let (ticket, mut random) = commit();
+        let expected = (0..4).map(|_| random.gen()).collect::<Vec<_>>();
+        let (epoch, commitment) = commitment();
+
+        assert_eq!(replay(ticket).err(), Some(VerifyError::NotRevealed));
+
+        reseed();
+
+        let revealed = revealed();
+        let (_, seed) = revealed.iter().find(|(id, _)| *id == epoch).expect("To reveal seed");
+        let seed = Hex(seed).to_string();
+        assert_eq!(seed.len(), SEED_LEN * 2);
+
+        let (hash, mut replayed) = replay_with_seed(&seed, ticket.nonce).expect("To parse seed");
+        assert_eq!(hash, commitment);
+        assert_eq!((0..4).map(|_| replayed.gen()).collect::<Vec<_>>(), expected);
+
+        let mut replayed = replay(ticket).expect("To replay roll");
+        assert_eq!((0..4).map(|_| replayed.gen()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn should_reveal_seed_of_previous_run() {
+        let mut previous = State {
+            current: Epoch {
+                id: 1,
+                seed: [1; SEED_LEN],
+                nonce: 5,
+            },
+            revealed: VecDeque::new(),
+        };
+        previous.reseed();
+        let seeds = previous.seeds();
+        assert_eq!(seeds.revealed, [(1, [1; SEED_LEN])]);
+
+        let mut state = State {
+            current: Epoch {
+                id: 0,
+                seed: [0; SEED_LEN],
+                nonce: 0,
+            },
+            revealed: VecDeque::new(),
+        };
+        state.restore(seeds.clone());
+
+        let (id, seed) = seeds.current.expect("To have current seed");
+        assert_eq!(state.revealed.len(), 2);
+        assert_eq!(state.revealed.back(), Some(&(id, seed)));
+        assert!(state.current.id > id);
+        assert_ne!(state.current.seed, seed);
+
+        state.restore(Seeds::default());
+        assert!(state.revealed.is_empty());
+    }
+
+    #[test]
+    fn should_use_distinct_nonces() {
+        let (first, mut first_random) = commit();
+        let (second, mut second_random) = commit();
+
+        assert_ne!(first, second);
+        assert_ne!(first_random.gen(), second_random.gen());
+    }
+
+    #[test]
+    fn should_parse_ticket_and_seed() {
+        assert_eq!(Ticket::from_str("#12.34"), Some(Ticket { epoch: 12, nonce: 34 }));
+        assert_eq!(Ticket::from_str("12.34"), Some(Ticket { epoch: 12, nonce: 34 }));
+        assert_eq!(Ticket { epoch: 12, nonce: 34 }.to_string(), "#12.34");
+        assert_eq!(Ticket::from_str("12"), None);
+        assert_eq!(Ticket::from_str("a.b"), None);
+
+        assert!(replay_with_seed(&"ab".repeat(SEED_LEN), 0).is_some());
+        assert!(replay_with_seed("ab", 0).is_none());
+        assert!(replay_with_seed(&"zz".repeat(SEED_LEN), 0).is_none());
+    }
+}
@@ -1,8 +1,26 @@
-use get_random_const::random;
+use core::future::Future;
 
-#[random]
-const RANDOM_SEED: u64 = 0;
+lazy_static::lazy_static! {
+    ///Runtime shared by discord, twitter and all other async tasks.
+    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2)
+                                                                                            .thread_name("jeanne-rt")
+                                                                                            .enable_all()
+                                                                                            .build()
+                                                                                            .expect("To create tokio runtime");
+}
 
 pub fn init() {
-    cute_dnd_dice::set_seed(RANDOM_SEED);
+    lazy_static::initialize(&RUNTIME);
+}
+
+#[inline]
+///Spawns task on shared runtime.
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(fut: F) {
+    RUNTIME.spawn(fut);
+}
+
+#[inline]
+///Runs future to completion on shared runtime, blocking current thread.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    RUNTIME.block_on(fut)
 }
@@ -0,0 +1,98 @@
+use crate::{constants, stats};
+use crate::health::Report;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::io;
+use std::env;
+
+const READ_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(5);
+
+async fn respond_with(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+#[inline(always)]
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    respond_with(stream, status, "text/plain; charset=utf-8", body).await
+}
+
+async fn handle(mut stream: TcpStream) -> io::Result<()> {
+    let mut line = String::new();
+    match tokio::time::timeout(READ_TIMEOUT, BufReader::new(&mut stream).read_line(&mut line)).await {
+        Ok(res) => res?,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "Request is not received in time")),
+    };
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "").await;
+    }
+
+    match path {
+        "/healthz" => {
+            let report = Report::collect().await;
+            let status = match report.is_alive() {
+                true => "200 OK",
+                false => "503 Service Unavailable",
+            };
+            respond(&mut stream, status, &report.to_string()).await
+        },
+        "/readyz" => {
+            let report = Report::collect().await;
+            let status = match report.is_ready() {
+                true => "200 OK",
+                false => "503 Service Unavailable",
+            };
+            respond(&mut stream, status, &report.to_string()).await
+        },
+        "/metrics" => {
+            let mut metrics = stats::CounterMetrics(&stats::STATS).to_string();
+            metrics.push_str(&stats::LatencyMetrics(&stats::LATENCY).to_string());
+            stats::COMMANDS.with_read(|commands| metrics.push_str(&stats::Metrics(commands).to_string()));
+            respond(&mut stream, "200 OK", &metrics).await
+        },
+        "/stats" => match serde_json::to_string(&stats::STATS) {
+            Ok(json) => respond_with(&mut stream, "200 OK", "application/json", &json).await,
+            Err(error) => respond(&mut stream, "500 Internal Server Error", &error.to_string()).await,
+        },
+        _ => respond(&mut stream, "404 Not Found", "").await,
+    }
+}
+
+///Runs HTTP server, serving health, metrics and stats endpoints.
+///
+///Listens on `JEANNE_HEALTH_ADDR` or `constants::HEALTH_ADDR` if it is not set.
+pub async fn run() {
+    let addr = env::var("JEANNE_HEALTH_ADDR").unwrap_or_else(|_| constants::HEALTH_ADDR.to_owned());
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            log::error!("Unable to start health server on {}: {}", addr, error);
+            return;
+        }
+    };
+
+    log::info!("Health server listens on {}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(error) = handle(stream).await {
+                        log::debug!("Health request failed: {}", error);
+                    }
+                });
+            },
+            Err(error) => {
+                log::warn!("Health server unable to accept connection: {}", error);
+            }
+        }
+    }
+}
@@ -2,6 +2,11 @@ use core::sync::atomic;
 use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::ser::{Serialize, Serializer, SerializeStruct};
 
 type Integer = atomic::AtomicUsize;
 
@@ -9,174 +14,856 @@ const fn default_integer() -> Integer {
     Integer::new(0)
 }
 
-pub trait CounterType {
+///Value stored within `Stats`
+pub trait Metric {
     fn get_ref(stats: &Stats) -> &Integer;
 }
 
+///Monotonic counter, that can only be increased.
+pub trait CounterType: Metric {}
+
+///Gauge, that holds current value.
+pub trait GaugeType: Metric {}
+
+///Counter's metadata
+pub struct Counter {
+    ///Name of group counter belongs to.
+    pub group: &'static str,
+    ///Name of counter within group.
+    pub name: &'static str,
+    ///Human readable description.
+    pub description: &'static str,
+    ///Whether it is gauge rather than counter.
+    pub is_gauge: bool,
+    get_ref: fn(&Stats) -> &Integer,
+}
+
+impl Counter {
+    #[inline]
+    pub fn get(&self, stats: &Stats) -> usize {
+        (self.get_ref)(stats).load(atomic::Ordering::Acquire)
+    }
+}
+
+macro_rules! is_gauge {
+    () => { false };
+    (gauge) => { true };
+}
+
+macro_rules! impl_kind {
+    ($name:ident) => { impl CounterType for $name {} };
+    ($name:ident gauge) => { impl GaugeType for $name {} };
+}
+
 macro_rules! impl_counter {
-    ($($name:ident: $($path:tt).+;)+) => {
+    ($(
+        $(#[doc = $group_doc:literal])*
+        $group:ident($group_field:ident) {
+            $(
+                $(#[doc = $doc:literal])+
+                $name:ident: $field:ident $(($kind:ident))?;
+            )+
+        }
+    )+) => {
         $(
-            pub struct $name;
-            impl CounterType for $name {
-                fn get_ref(stats: &Stats) -> &Integer {
-                    &stats.$($path).+
+            $(#[doc = $group_doc])*
+            #[derive(Debug)]
+            pub struct $group {
+                $(
+                    $(#[doc = $doc])+
+                    pub $field: Integer,
+                )+
+            }
+
+            impl $group {
+                const fn new() -> Self {
+                    Self {
+                        $(
+                            $field: default_integer(),
+                        )+
+                    }
                 }
             }
+
+            impl Serialize for $group {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut state = serializer.serialize_struct(stringify!($group), [$(stringify!($field)),+].len())?;
+                    $(
+                        state.serialize_field(stringify!($field), &self.$field.load(atomic::Ordering::Acquire))?;
+                    )+
+                    state.end()
+                }
+            }
+
+            $(
+                $(#[doc = $doc])+
+                pub struct $name;
+                impl Metric for $name {
+                    fn get_ref(stats: &Stats) -> &Integer {
+                        &stats.$group_field.$field
+                    }
+                }
+                impl_kind!($name $($kind)?);
+            )+
         )+
 
-            impl Stats {
-                pub fn reset(&self) {
+        pub struct Stats {
+            $(
+                pub $group_field: $group,
+            )+
+        }
+
+        impl Stats {
+            const fn new() -> Self {
+                Self {
                     $(
-                        $name::get_ref(self).store(0, atomic::Ordering::Release);
-                     )+
+                        $group_field: $group::new(),
+                    )+
                 }
             }
+
+        }
+
+        impl Serialize for Stats {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut state = serializer.serialize_struct("Stats", [$(stringify!($group_field)),+].len())?;
+                $(
+                    state.serialize_field(stringify!($group_field), &self.$group_field)?;
+                )+
+                state.end()
+            }
+        }
+
+        ///Metadata of all counters, in order of declaration.
+        pub static COUNTERS: &[Counter] = &[
+            $(
+                $(
+                    Counter {
+                        group: stringify!($group_field),
+                        name: stringify!($field),
+                        description: concat!($($doc),+),
+                        is_gauge: is_gauge!($($kind)?),
+                        get_ref: $name::get_ref,
+                    },
+                )+
+            )+
+        ];
     }
 }
 
 impl_counter!(
-    DiscordConnected: discord.connected;
-    DiscordReConnected: discord.re_connected;
-    DiscordBrokenConfigUpdate: discord.broken_config_update;
-    DiscordNoAppInfo: discord.no_app_info;
-    DiscordMsgReject: discord.msg_reject;
-    DiscordMsgFail: discord.msg_fail;
-    DiscordShutdown: discord.shutdown;
-    DiscordFailure: discord.failure;
-    DiscordCmdNum: discord.cmd_count;
-    DiscordNewMember: discord.new_member;
-    DiscordLossMember: discord.loss_member;
-    TwitterStartStream: twitter.start_stream;
-    TwitterRetweet: twitter.retweet;
-    TwitterUnfilteredTweet: twitter.unfiltered_tweet;
-    TwitterUntrustedTweet: twitter.untrusted_tweet;
-    TwitterPeriodicTweet: twitter.periodic_tweet;
+    Discord(discord) {
+        ///Discord has been connected.
+        DiscordConnected: connected;
+        ///Discord has been re-connected.
+        DiscordReConnected: re_connected;
+        ///Unable to update configuration file.
+        DiscordBrokenConfigUpdate: broken_config_update;
+        ///Failed to retrieve application info.
+        DiscordNoAppInfo: no_app_info;
+        ///Message is rejected by Discord
+        DiscordMsgReject: msg_reject;
+        ///Failed to send message
+        DiscordMsgFail: msg_fail;
+        ///Serenity is gracefully shut down
+        DiscordShutdown: shutdown;
+        ///Serenity aborted with error
+        DiscordFailure: failure;
+        ///Number of commands
+        DiscordCmdNum: cmd_count;
+        ///Number of new members
+        DiscordNewMember: new_member;
+        ///Number of removed members
+        DiscordLossMember: loss_member;
+        ///Number of guilds bot is in
+        DiscordGuilds: guilds(gauge);
+    }
+    Twitter(twitter) {
+        ///Number of times, twitter's stream has been started
+        TwitterStartStream: start_stream;
+        ///Number of times redirected tweet.
+        TwitterRetweet: retweet;
+        ///Number of times when incoming tweet was discarded due unmatching hash tags.
+        TwitterUnfilteredTweet: unfiltered_tweet;
+        ///Number of times when incoming tweet was discarded due not being from trusted user.
+        TwitterUntrustedTweet: untrusted_tweet;
+        ///Number of times when you  post tweet.
+        TwitterPeriodicTweet: periodic_tweet;
+    }
 );
 
-#[derive(Debug)]
-pub struct Twitter {
-    ///Number of times, twitter's stream has been started
-    pub start_stream: Integer,
-    ///Number of times redirected tweet.
-    pub retweet: Integer,
-    ///Number of times when incoming tweet was discarded due unmatching hash tags.
-    pub unfiltered_tweet: Integer,
-    ///Number of times when incoming tweet was discarded due not being from trusted user.
-    pub untrusted_tweet: Integer,
-    ///Number of times when you  post tweet.
-    pub periodic_tweet: Integer,
-}
-
-#[derive(Debug)]
-pub struct Discord {
-    ///Discord has been connected.
-    pub connected: Integer,
-    ///Discord has been re-connected.
-    pub re_connected: Integer,
-    ///Unable to update configuration file.
-    pub broken_config_update: Integer,
-    ///Failed to retrieve application info.
-    pub no_app_info: Integer,
-    ///Message is rejected by Discord
-    pub msg_reject: Integer,
-    ///Failed to send message
-    pub msg_fail: Integer,
-    ///Serenity is gracefully shut down
-    pub shutdown: Integer,
-    ///Serenity aborted with error
-    pub failure: Integer,
-    ///Number of commands
-    pub cmd_count: Integer,
-    ///Number of new members
-    pub new_member: Integer,
-    ///Number of removed members
-    pub loss_member: Integer,
-}
-
-impl fmt::Display for Twitter {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "start_stream:     **{}**\n", self.start_stream.load(atomic::Ordering::Acquire))?;
-        write!(f, "retweet:          **{}**\n", self.retweet.load(atomic::Ordering::Acquire))?;
-        write!(f, "unfiltered_tweet: **{}**\n", self.unfiltered_tweet.load(atomic::Ordering::Acquire))?;
-        write!(f, "untrusted_tweet:  **{}**\n", self.untrusted_tweet.load(atomic::Ordering::Acquire))?;
-        write!(f, "periodic_tweet:   **{}**\n", self.periodic_tweet.load(atomic::Ordering::Acquire))?;
+impl Stats {
+    ///Increments value of counter, and returns its old value.
+    #[inline]
+    pub fn increment<C: CounterType>(&self, counter: C) -> usize {
+        self.add(counter, 1)
+    }
 
-        Ok(())
+    ///Adds `num` to value of counter, and returns its old value.
+    #[inline]
+    pub fn add<C: CounterType>(&self, _: C, num: usize) -> usize {
+        C::get_ref(self).fetch_add(num, atomic::Ordering::AcqRel)
+    }
+
+    ///Sets value of gauge, and returns its old value.
+    #[inline]
+    pub fn set<G: GaugeType>(&self, _: G, value: usize) -> usize {
+        G::get_ref(self).swap(value, atomic::Ordering::AcqRel)
+    }
+
+    ///Creates guard that increments counter on drop, unless cancelled via `StatIncrement::forget`.
+    #[allow(unused)]
+    pub fn deferred_increment<C: CounterType>(&self, _: C) -> StatIncrement<'_, C> {
+        StatIncrement {
+            stats: self,
+            counter: PhantomData
+        }
+    }
+}
+
+pub struct StatIncrement<'a, C: CounterType> {
+    stats: &'a Stats,
+    counter: PhantomData<C>
+}
+
+impl<C: CounterType> StatIncrement<'_, C> {
+    ///Cancels increment.
+    #[allow(unused)]
+    pub fn forget(self) {
+        mem::forget(self)
+    }
+}
+
+impl<C: CounterType> Drop for StatIncrement<'_, C> {
+    fn drop(&mut self) {
+        C::get_ref(self.stats).fetch_add(1, atomic::Ordering::AcqRel);
     }
 }
 
-impl fmt::Display for Discord {
+pub static STATS: Stats = Stats::new();
+
+impl Stats {
+    ///Returns current values of all counters.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot(COUNTERS.iter().map(|counter| counter.get(self)).collect())
+    }
+
+    ///Returns change of counters since previous call.
+    ///
+    ///Counters are kept intact, as they are exported as monotonic metrics.
+    pub fn take_delta(&self) -> Snapshot {
+        let snapshot = self.snapshot();
+        let mut shown = SHOWN_STATS.lock();
+
+        let delta = snapshot.delta(&shown);
+        *shown = snapshot;
+        delta
+    }
+}
+
+///Values of all counters, in order of `COUNTERS`.
+#[derive(Clone)]
+pub struct Snapshot(Vec<usize>);
+
+impl Snapshot {
+    ///Returns change since `last`, keeping current value of gauges.
+    pub fn delta(&self, last: &Snapshot) -> Self {
+        Snapshot(COUNTERS.iter().zip(self.0.iter().zip(last.0.iter())).map(|(counter, (value, last))| match counter.is_gauge {
+            true => *value,
+            false => value.saturating_sub(*last),
+        }).collect())
+    }
+
+    ///Returns counters of `group` for display.
+    pub fn group<'a>(&'a self, group: &'a str) -> SnapshotGroup<'a> {
+        SnapshotGroup {
+            snapshot: self,
+            group,
+        }
+    }
+}
+
+pub struct SnapshotGroup<'a> {
+    snapshot: &'a Snapshot,
+    group: &'a str,
+}
+
+impl fmt::Display for SnapshotGroup<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "connected:            **{}**\n", self.connected.load(atomic::Ordering::Acquire))?;
-        write!(f, "re_connected:         **{}**\n", self.re_connected.load(atomic::Ordering::Acquire))?;
-        write!(f, "broken_config_update: **{}**\n", self.broken_config_update.load(atomic::Ordering::Acquire))?;
-        write!(f, "no_app_info:          **{}**\n", self.no_app_info.load(atomic::Ordering::Acquire))?;
-        write!(f, "msg_reject:           **{}**\n", self.msg_reject.load(atomic::Ordering::Acquire))?;
-        write!(f, "msg_fail:             **{}**\n", self.msg_fail.load(atomic::Ordering::Acquire))?;
-        write!(f, "shutdown:             **{}**\n", self.shutdown.load(atomic::Ordering::Acquire))?;
-        write!(f, "failure:              **{}**\n", self.failure.load(atomic::Ordering::Acquire))?;
-        write!(f, "cmd_count:            **{}**\n", self.cmd_count.load(atomic::Ordering::Acquire))?;
-        write!(f, "new_member:           **{}**\n", self.new_member.load(atomic::Ordering::Acquire))?;
-        write!(f, "loss_member:          **{}**\n", self.loss_member.load(atomic::Ordering::Acquire))?;
+        let counters = || COUNTERS.iter().zip(self.snapshot.0.iter()).filter(|(counter, _)| counter.group == self.group);
+        let width = counters().map(|(counter, _)| counter.name.len()).max().unwrap_or(0) + 1;
+
+        for (counter, value) in counters() {
+            writeln!(f, "{}:{:pad$} **{}**", counter.name, "", value, pad = width - counter.name.len() - 1)?;
+        }
 
         Ok(())
     }
 }
 
-pub struct Stats {
-    pub discord: Discord,
-    pub twitter: Twitter,
+///Upper bounds of histogram's buckets in milliseconds, last bucket is unbounded.
+const BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+///Latency histogram with fixed buckets.
+pub struct Histogram {
+    buckets: [Integer; BUCKETS_MS.len() + 1],
+    count: Integer,
+    sum_ms: Integer,
 }
 
-impl Stats {
+impl Histogram {
     const fn new() -> Self {
-        Stats {
-            discord: Discord {
-                connected: default_integer(),
-                re_connected: default_integer(),
-                broken_config_update: default_integer(),
-                no_app_info: default_integer(),
-                msg_reject: default_integer(),
-                msg_fail: default_integer(),
-                shutdown: default_integer(),
-                failure: default_integer(),
-                cmd_count: default_integer(),
-                new_member: default_integer(),
-                loss_member: default_integer(),
-            },
-            twitter: Twitter {
-                start_stream: default_integer(),
-                retweet: default_integer(),
-                unfiltered_tweet: default_integer(),
-                untrusted_tweet: default_integer(),
-                periodic_tweet: default_integer(),
+        Self {
+            buckets: [
+                default_integer(), default_integer(), default_integer(), default_integer(),
+                default_integer(), default_integer(), default_integer(), default_integer(),
+                default_integer(), default_integer(), default_integer(), default_integer(),
+            ],
+            count: default_integer(),
+            sum_ms: default_integer(),
+        }
+    }
+
+    pub fn record(&self, time: Duration) {
+        let time = time.as_millis() as u64;
+        let idx = BUCKETS_MS.iter().position(|bound| time <= *bound).unwrap_or(BUCKETS_MS.len());
+
+        self.buckets[idx].fetch_add(1, atomic::Ordering::AcqRel);
+        self.count.fetch_add(1, atomic::Ordering::AcqRel);
+        self.sum_ms.fetch_add(time as usize, atomic::Ordering::AcqRel);
+    }
+
+    ///Awaits `fut` and records time it took.
+    #[inline]
+    pub async fn time<F: core::future::Future>(&self, fut: F) -> F::Output {
+        let start = Instant::now();
+        let res = fut.await;
+        self.record(start.elapsed());
+        res
+    }
+
+    ///Returns current state of histogram.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut buckets = [0; BUCKETS_MS.len() + 1];
+        for (value, bucket) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *value = bucket.load(atomic::Ordering::Acquire);
+        }
+
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(atomic::Ordering::Acquire),
+            sum_ms: self.sum_ms.load(atomic::Ordering::Acquire),
+        }
+    }
+}
+
+///State of histogram at some moment.
+#[derive(Clone, Default)]
+pub struct HistogramSnapshot {
+    buckets: [usize; BUCKETS_MS.len() + 1],
+    count: usize,
+    sum_ms: usize,
+}
+
+impl HistogramSnapshot {
+    ///Returns values recorded since `last`.
+    pub fn delta(&self, last: &HistogramSnapshot) -> Self {
+        let mut buckets = self.buckets;
+        for (value, last) in buckets.iter_mut().zip(last.buckets.iter()) {
+            *value = value.saturating_sub(*last);
+        }
+
+        Self {
+            buckets,
+            count: self.count.saturating_sub(last.count),
+            sum_ms: self.sum_ms.saturating_sub(last.sum_ms),
+        }
+    }
+
+    ///Returns estimated percentile in milliseconds as upper bound of bucket it falls into.
+    ///
+    ///Returns `None` if there is no recorded values or percentile falls into unbounded bucket.
+    pub fn percentile(&self, percent: usize) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (self.count * percent).div_ceil(100);
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return BUCKETS_MS.get(idx).copied();
             }
         }
+
+        None
     }
+}
 
-    ///Increments value of counter, and returns its old value.
-    pub fn increment<C: CounterType>(&self, _: C) -> StatIncrement<C> {
-        StatIncrement {
-            counter: PhantomData
+struct Percentile(Option<u64>);
+
+impl fmt::Display for Percentile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}ms", value),
+            None => write!(f, ">{}ms", BUCKETS_MS[BUCKETS_MS.len() - 1]),
         }
     }
 }
 
-pub struct StatIncrement<C: CounterType> {
-    counter: PhantomData<C>
+impl fmt::Display for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.count {
+            0 => f.write_str("**0**"),
+            count => write!(f, "**{}** p50 **{}** p90 **{}** p99 **{}**", count, Percentile(self.percentile(50)), Percentile(self.percentile(90)), Percentile(self.percentile(99))),
+        }
+    }
 }
 
-impl<C: CounterType> StatIncrement<C> {
-    #[allow(unused)]
-    pub fn forget(self) {
-        mem::forget(self)
+pub struct Latency {
+    ///`ChannelId::say`
+    pub discord_say: Histogram,
+    ///`Message::reply`
+    pub discord_reply: Histogram,
+    ///Retweet
+    pub twitter_retweet: Histogram,
+    ///Post new tweet
+    pub twitter_tweet: Histogram,
+}
+
+pub static LATENCY: Latency = Latency {
+    discord_say: Histogram::new(),
+    discord_reply: Histogram::new(),
+    twitter_retweet: Histogram::new(),
+    twitter_tweet: Histogram::new(),
+};
+
+impl Latency {
+    ///Returns histograms with their names.
+    pub fn histograms(&self) -> [(&'static str, &Histogram); 4] {
+        [
+            ("discord_say", &self.discord_say),
+            ("discord_reply", &self.discord_reply),
+            ("twitter_retweet", &self.twitter_retweet),
+            ("twitter_tweet", &self.twitter_tweet),
+        ]
+    }
+
+    ///Returns current state of all histograms.
+    pub fn snapshot(&self) -> LatencySnapshot {
+        let histograms = self.histograms();
+        LatencySnapshot([
+            (histograms[0].0, histograms[0].1.snapshot()),
+            (histograms[1].0, histograms[1].1.snapshot()),
+            (histograms[2].0, histograms[2].1.snapshot()),
+            (histograms[3].0, histograms[3].1.snapshot()),
+        ])
+    }
+
+    ///Returns latency recorded since previous call.
+    ///
+    ///Histograms are kept intact, as they are exported as cumulative metrics.
+    pub fn take_delta(&self) -> LatencySnapshot {
+        let snapshot = self.snapshot();
+        let mut shown = SHOWN_LATENCY.lock();
+
+        let delta = snapshot.delta(&shown);
+        *shown = snapshot;
+        delta
     }
 }
 
-impl<C: CounterType> Drop for StatIncrement<C> {
-    fn drop(&mut self) {
-        C::get_ref(&STATS).fetch_add(1, atomic::Ordering::AcqRel);
+///State of all latency histograms with their names.
+#[derive(Clone, Default)]
+pub struct LatencySnapshot([(&'static str, HistogramSnapshot); 4]);
+
+impl LatencySnapshot {
+    ///Returns latency recorded since `last`.
+    pub fn delta(&self, last: &LatencySnapshot) -> Self {
+        let mut result = self.clone();
+        for ((_, histogram), (_, last)) in result.0.iter_mut().zip(last.0.iter()) {
+            *histogram = histogram.delta(last);
+        }
+        result
     }
 }
 
-pub static STATS: Stats = Stats::new();
+impl fmt::Display for LatencySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, histogram) in self.0.iter() {
+            writeln!(f, "{}: {}", name, histogram)?;
+        }
+
+        Ok(())
+    }
+}
+
+///Formats latency histograms in prometheus text format
+pub struct LatencyMetrics<'a>(pub &'a Latency);
+
+impl fmt::Display for LatencyMetrics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# TYPE jeanne_latency_seconds histogram")?;
+        for (name, histogram) in self.0.histograms().iter() {
+            let mut cumulative = 0;
+            for (bound, bucket) in BUCKETS_MS.iter().zip(histogram.buckets.iter()) {
+                cumulative += bucket.load(atomic::Ordering::Acquire);
+                writeln!(f, "jeanne_latency_seconds_bucket{{call=\"{}\",le=\"{}\"}} {}", name, *bound as f64 / 1000.0, cumulative)?;
+            }
+            writeln!(f, "jeanne_latency_seconds_bucket{{call=\"{}\",le=\"+Inf\"}} {}", name, histogram.count.load(atomic::Ordering::Acquire))?;
+            writeln!(f, "jeanne_latency_seconds_sum{{call=\"{}\"}} {}", name, histogram.sum_ms.load(atomic::Ordering::Acquire) as f64 / 1000.0)?;
+            writeln!(f, "jeanne_latency_seconds_count{{call=\"{}\"}} {}", name, histogram.count.load(atomic::Ordering::Acquire))?;
+        }
+
+        Ok(())
+    }
+}
+
+///Maximum number of entries to display
+const COMMANDS_DISPLAY_LIMIT: usize = 15;
+
+#[derive(Default, Clone)]
+pub struct CommandStat {
+    ///Number of invocations
+    pub count: usize,
+    ///Number of invocations that returned error
+    pub failures: usize,
+    ///Total execution time
+    pub total_time: Duration,
+    ///Longest execution time
+    pub max_time: Duration,
+}
+
+impl CommandStat {
+    fn add(&mut self, is_fail: bool, time: Duration) {
+        self.count += 1;
+        if is_fail {
+            self.failures += 1;
+        }
+        self.total_time += time;
+        if time > self.max_time {
+            self.max_time = time;
+        }
+    }
+
+    ///Returns change since `last`, keeping maximum time as it is.
+    fn delta(&self, last: &CommandStat) -> Self {
+        Self {
+            count: self.count.saturating_sub(last.count),
+            failures: self.failures.saturating_sub(last.failures),
+            total_time: self.total_time.checked_sub(last.total_time).unwrap_or_default(),
+            max_time: self.max_time,
+        }
+    }
+
+    pub fn avg_time(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            count => self.total_time / count as u32,
+        }
+    }
+}
+
+impl fmt::Display for CommandStat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "**{}** (failed **{}**) avg **{}ms** max **{}ms**", self.count, self.failures, self.avg_time().as_millis(), self.max_time.as_millis())
+    }
+}
+
+fn delta<K: Clone + Eq + core::hash::Hash>(stats: &HashMap<K, CommandStat>, last: &HashMap<K, CommandStat>) -> HashMap<K, CommandStat> {
+    stats.iter().filter_map(|(key, stat)| {
+        let stat = match last.get(key) {
+            Some(last) => stat.delta(last),
+            None => stat.clone(),
+        };
+
+        match stat.count {
+            0 => None,
+            _ => Some((key.clone(), stat)),
+        }
+    }).collect()
+}
+
+#[derive(Default, Clone)]
+pub struct CommandsInner {
+    pub by_name: HashMap<String, CommandStat>,
+    pub by_guild: HashMap<u64, CommandStat>,
+}
+
+impl CommandsInner {
+    ///Returns usage since `last`, skipping unused entries.
+    pub fn delta(&self, last: &CommandsInner) -> Self {
+        Self {
+            by_name: delta(&self.by_name, &last.by_name),
+            by_guild: delta(&self.by_guild, &last.by_guild),
+        }
+    }
+}
+
+///Per command usage statistics
+pub struct Commands {
+    inner: parking_lot::Mutex<CommandsInner>,
+    ///Statistics at the moment of last `take_delta`
+    shown: parking_lot::Mutex<CommandsInner>,
+}
+
+impl Commands {
+    fn new() -> Self {
+        Self {
+            inner: parking_lot::Mutex::new(CommandsInner::default()),
+            shown: parking_lot::Mutex::new(CommandsInner::default()),
+        }
+    }
+
+    ///Records command execution.
+    ///
+    ///Guild is `0` for direct messages.
+    pub fn record(&self, name: &str, guild: u64, is_fail: bool, time: Duration) {
+        let mut inner = self.inner.lock();
+
+        match inner.by_name.get_mut(name) {
+            Some(stat) => stat.add(is_fail, time),
+            None => {
+                let mut stat = CommandStat::default();
+                stat.add(is_fail, time);
+                inner.by_name.insert(name.to_owned(), stat);
+            }
+        }
+
+        inner.by_guild.entry(guild).or_default().add(is_fail, time);
+    }
+
+    #[inline]
+    pub fn with_read<R, F: FnOnce(&CommandsInner) -> R>(&self, cb: F) -> R {
+        cb(&self.inner.lock())
+    }
+
+    ///Returns usage since previous call.
+    ///
+    ///Totals are kept intact, as they are exported as monotonic metrics.
+    pub fn take_delta(&self) -> CommandsInner {
+        let inner = self.inner.lock().clone();
+        let mut shown = self.shown.lock();
+
+        let delta = inner.delta(&shown);
+        *shown = inner;
+        delta
+    }
+}
+
+fn fmt_sorted<K: fmt::Display>(f: &mut fmt::Formatter, stats: &HashMap<K, CommandStat>) -> fmt::Result {
+    if stats.is_empty() {
+        return f.write_str("None");
+    }
+
+    let mut stats: Vec<_> = stats.iter().collect();
+    stats.sort_unstable_by_key(|(_, stat)| core::cmp::Reverse(stat.count));
+
+    for (key, stat) in stats.iter().take(COMMANDS_DISPLAY_LIMIT) {
+        writeln!(f, "`{}`: {}", key, stat)?;
+    }
+
+    if stats.len() > COMMANDS_DISPLAY_LIMIT {
+        write!(f, "...and {} more", stats.len() - COMMANDS_DISPLAY_LIMIT)?;
+    }
+
+    Ok(())
+}
+
+///Formats command statistics by command name
+pub struct ByName<'a>(pub &'a CommandsInner);
+
+impl fmt::Display for ByName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_sorted(f, &self.0.by_name)
+    }
+}
+
+///Formats command statistics by guild
+pub struct ByGuild<'a>(pub &'a CommandsInner);
+
+impl fmt::Display for ByGuild<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_sorted(f, &self.0.by_guild)
+    }
+}
+
+///Formats counters in prometheus text format
+pub struct CounterMetrics<'a>(pub &'a Stats);
+
+impl fmt::Display for CounterMetrics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for counter in COUNTERS.iter() {
+            writeln!(f, "# HELP jeanne_{}_{} {}", counter.group, counter.name, counter.description.trim())?;
+            writeln!(f, "# TYPE jeanne_{}_{} {}", counter.group, counter.name, match counter.is_gauge {
+                true => "gauge",
+                false => "counter",
+            })?;
+            writeln!(f, "jeanne_{}_{} {}", counter.group, counter.name, counter.get(self.0))?;
+        }
+
+        Ok(())
+    }
+}
+
+///Formats command statistics in prometheus text format
+pub struct Metrics<'a>(pub &'a CommandsInner);
+
+impl fmt::Display for Metrics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# TYPE jeanne_command_total counter")?;
+        for (name, stat) in self.0.by_name.iter() {
+            writeln!(f, "jeanne_command_total{{command=\"{}\"}} {}", name, stat.count)?;
+        }
+        writeln!(f, "# TYPE jeanne_command_failures_total counter")?;
+        for (name, stat) in self.0.by_name.iter() {
+            writeln!(f, "jeanne_command_failures_total{{command=\"{}\"}} {}", name, stat.failures)?;
+        }
+        writeln!(f, "# TYPE jeanne_command_seconds_total counter")?;
+        for (name, stat) in self.0.by_name.iter() {
+            writeln!(f, "jeanne_command_seconds_total{{command=\"{}\"}} {}", name, stat.total_time.as_secs_f64())?;
+        }
+        writeln!(f, "# TYPE jeanne_command_seconds_max gauge")?;
+        for (name, stat) in self.0.by_name.iter() {
+            writeln!(f, "jeanne_command_seconds_max{{command=\"{}\"}} {}", name, stat.max_time.as_secs_f64())?;
+        }
+        writeln!(f, "# TYPE jeanne_guild_command_total counter")?;
+        for (guild, stat) in self.0.by_guild.iter() {
+            writeln!(f, "jeanne_guild_command_total{{guild=\"{}\"}} {}", guild, stat.count)?;
+        }
+        writeln!(f, "# TYPE jeanne_guild_command_failures_total counter")?;
+        for (guild, stat) in self.0.by_guild.iter() {
+            writeln!(f, "jeanne_guild_command_failures_total{{guild=\"{}\"}} {}", guild, stat.failures)?;
+        }
+
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    ///Latency at the moment of last `Latency::take_delta`
+    static ref SHOWN_LATENCY: parking_lot::Mutex<LatencySnapshot> = parking_lot::Mutex::new(LatencySnapshot::default());
+    ///Counters at the moment of last `Stats::take_delta`
+    static ref SHOWN_STATS: parking_lot::Mutex<Snapshot> = parking_lot::Mutex::new(Snapshot(vec![0; COUNTERS.len()]));
+    pub static ref COMMANDS: Commands = Commands::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_show_commands_delta_without_resetting_totals() {
+        let commands = Commands::new();
+        commands.record("dice", 1, false, Duration::from_millis(10));
+        commands.record("dice", 2, true, Duration::from_millis(30));
+
+        let delta = commands.take_delta();
+        assert_eq!(delta.by_name["dice"].count, 2);
+        assert_eq!(delta.by_name["dice"].failures, 1);
+        assert_eq!(delta.by_guild.len(), 2);
+
+        commands.record("dice", 1, false, Duration::from_millis(20));
+        let delta = commands.take_delta();
+        assert_eq!(delta.by_name["dice"].count, 1);
+        assert_eq!(delta.by_name["dice"].failures, 0);
+        assert_eq!(delta.by_name["dice"].total_time, Duration::from_millis(20));
+        assert_eq!(delta.by_name["dice"].max_time, Duration::from_millis(30));
+        //Unused guild is skipped
+        assert_eq!(delta.by_guild.keys().collect::<Vec<_>>(), [&1]);
+
+        assert!(commands.take_delta().by_name.is_empty());
+
+        let metrics = commands.with_read(|commands| Metrics(commands).to_string());
+        assert!(metrics.contains("jeanne_command_total{command=\"dice\"} 3\n"));
+        assert!(metrics.contains("jeanne_command_failures_total{command=\"dice\"} 1\n"));
+        assert!(metrics.contains("jeanne_guild_command_total{guild=\"1\"} 2\n"));
+    }
+
+    #[test]
+    fn should_increment_on_drop_unless_forgotten() {
+        let stats = Stats::new();
+        let value = || DiscordNewMember::get_ref(&stats).load(atomic::Ordering::Acquire);
+
+        stats.deferred_increment(DiscordNewMember).forget();
+        assert_eq!(value(), 0);
+
+        {
+            let _guard = stats.deferred_increment(DiscordNewMember);
+            assert_eq!(value(), 0);
+        }
+        assert_eq!(value(), 1);
+    }
+
+    #[test]
+    fn should_show_counters_delta_keeping_gauges() {
+        let idx = |name: &str| COUNTERS.iter().position(|counter| counter.name == name).expect("To find counter");
+        let mut last = vec![0; COUNTERS.len()];
+        let mut current = last.clone();
+
+        last[idx("retweet")] = 2;
+        current[idx("retweet")] = 5;
+        last[idx("guilds")] = 4;
+        current[idx("guilds")] = 3;
+
+        let delta = Snapshot(current).delta(&Snapshot(last));
+        assert_eq!(delta.0[idx("retweet")], 3);
+        assert_eq!(delta.0[idx("guilds")], 3);
+
+        let text = delta.group("twitter").to_string();
+        assert!(text.starts_with("start_stream:     **0**\nretweet:          **3**\n"));
+        assert!(!text.contains("guilds"));
+    }
+
+    #[test]
+    fn should_show_latency_delta_keeping_cumulative_histogram() {
+        let latency = Latency {
+            discord_say: Histogram::new(),
+            discord_reply: Histogram::new(),
+            twitter_retweet: Histogram::new(),
+            twitter_tweet: Histogram::new(),
+        };
+
+        latency.discord_say.record(Duration::from_millis(3));
+        latency.discord_say.record(Duration::from_millis(20000));
+        let last = latency.snapshot();
+        latency.discord_say.record(Duration::from_millis(40));
+        latency.discord_say.record(Duration::from_millis(90));
+
+        let delta = latency.snapshot().delta(&last);
+        let say = &delta.0[0].1;
+        assert_eq!(say.count, 2);
+        assert_eq!(say.sum_ms, 130);
+        assert_eq!(say.percentile(50), Some(50));
+        assert_eq!(say.percentile(99), Some(100));
+        assert!(delta.to_string().starts_with("discord_say: **2** p50 **50ms** p90 **100ms** p99 **100ms**\ndiscord_reply: **0**\n"));
+
+        let metrics = LatencyMetrics(&latency).to_string();
+        assert!(metrics.contains("jeanne_latency_seconds_bucket{call=\"discord_say\",le=\"0.005\"} 1\n"));
+        assert!(metrics.contains("jeanne_latency_seconds_bucket{call=\"discord_say\",le=\"0.1\"} 3\n"));
+        assert!(metrics.contains("jeanne_latency_seconds_bucket{call=\"discord_say\",le=\"+Inf\"} 4\n"));
+        assert!(metrics.contains("jeanne_latency_seconds_count{call=\"discord_say\"} 4\n"));
+    }
+
+    #[test]
+    fn should_export_stats() {
+        let stats = Stats::new();
+        stats.increment(DiscordConnected);
+        stats.add(TwitterRetweet, 3);
+        assert_eq!(stats.set(DiscordGuilds, 5), 0);
+        assert_eq!(stats.set(DiscordGuilds, 4), 5);
+
+        let json: serde_json::Value = serde_json::to_value(&stats).expect("To serialize");
+        assert_eq!(json["discord"]["connected"], 1);
+        assert_eq!(json["discord"]["guilds"], 4);
+        assert_eq!(json["twitter"]["retweet"], 3);
+        assert_eq!(json["twitter"]["periodic_tweet"], 0);
+
+        let metrics = CounterMetrics(&stats).to_string();
+        assert!(metrics.contains("# HELP jeanne_discord_connected Discord has been connected.\n# TYPE jeanne_discord_connected counter\njeanne_discord_connected 1\n"));
+        assert!(metrics.contains("# TYPE jeanne_discord_guilds gauge\njeanne_discord_guilds 4\n"));
+        assert!(metrics.contains("# TYPE jeanne_twitter_retweet counter\njeanne_twitter_retweet 3\n"));
+        assert_eq!(metrics.lines().filter(|line| line.starts_with("# TYPE")).count(), COUNTERS.len());
+    }
+}
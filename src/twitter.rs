@@ -1,9 +1,11 @@
-use crate::{config, discord, constants};
+use crate::{config, discord, constants, health};
 use crate::stats::{self, STATS};
-use crate::utils::mpmc::Q64;
 
 use std::borrow::Cow;
 
+use egg_mode::stream::StreamMessage;
+use futures_util::stream::Stream;
+
 pub const TWITTER_CONSUMER_KEY: &str = env!("JEANNE_TWITTER_CONSUMER_KEY");
 pub const TWITTER_CONSUMER_SECRET: &str = env!("JEANNE_TWITTER_CONSUMER_SECRET");
 pub const TWITTER_ACCESS_KEY: &str = env!("JEANNE_ACCESS_CONSUMER_KEY");
@@ -26,152 +28,604 @@ const TOKEN: egg_mode::Token = egg_mode::Token::Access {
     }
 };
 
-//Stores cached tweet data,
+const TWITTER_API: &str = "https://api.twitter.com";
+const TWITTER_STREAM: &str = "https://stream.twitter.com";
+
+///Base URLs of twitter endpoints
+#[derive(Clone, Debug)]
+pub struct Api {
+    ///REST API, used to tweet and retweet
+    pub rest: String,
+    ///Streaming API
+    pub stream: String,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Self {
+            rest: TWITTER_API.to_owned(),
+            stream: TWITTER_STREAM.to_owned(),
+        }
+    }
+}
+
+//Number of tweets to keep while discord is unavailable,
 //we most likely do not need such big capacity
 //but just in case.
-pub static BUFFERED_TWEETS: Q64<(u64, String, TweetType)> = Q64::new();
+const BUFFERED_TWEETS: usize = 64;
+
+type TweetSender = tokio::sync::mpsc::Sender<(u64, String, TweetType)>;
+type TweetReceiver = tokio::sync::mpsc::Receiver<(u64, String, TweetType)>;
+
+fn create_twitter_stream(api: &Api) -> egg_mode::stream::TwitterStream {
+    let params = egg_mode::raw::ParamList::new().add_param("filter_level", egg_mode::stream::FilterLevel::None.to_string())
+                                                .add_param("track", "なぜ僕");
+    let req = egg_mode::raw::request_post(&format!("{}/1.1/statuses/filter.json", api.stream), &TOKEN, Some(&params));
 
-fn create_twitter_stream() -> egg_mode::stream::TwitterStream {
-    egg_mode::stream::filter().filter_level(egg_mode::stream::FilterLevel::None)
-                              .track(&["なぜ僕"])
-                              .start(&TOKEN)
+    egg_mode::raw::response_as_stream(req)
 }
 
+///Posts tweet with `text`.
+async fn tweet(api: &Api, text: &'static str) -> Result<egg_mode::Response<egg_mode::tweet::Tweet>, egg_mode::error::Error> {
+    let params = egg_mode::raw::ParamList::new().add_param("status", text)
+                                                .add_param("tweet_mode", "extended");
+    let req = egg_mode::raw::request_post(&format!("{}/1.1/statuses/update.json", api.rest), &TOKEN, Some(&params));
+
+    egg_mode::raw::response_json(req).await
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TweetType {
     NazeBoku,
 }
 
-fn send_tweet(http: &serenity::http::client::Http, id: u64, name: &str, ch_id: u64) {
+///Rule to pick tweets about topic
+pub struct Rule {
+    pub typ: TweetType,
+    ///Keyword to look for, without `#`
+    pub keyword: &'static str,
+    ///Sorted ids of users, who may mention keyword without hashtag
+    pub trusted: &'static [u64],
+    ///Whether to retweet matching tweets
+    pub retweet: bool,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        typ: TweetType::NazeBoku,
+        keyword: "なぜ僕",
+        trusted: &TRUST_USER_IDS,
+        retweet: true,
+    },
+];
+
+///Reason to ignore tweet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reject {
+    Retweet,
+    Reply,
+    NoAuthor,
+    ///Keyword is mentioned without hashtag by untrusted user
+    Untrusted,
+    ///No rule matches
+    Unfiltered,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision<'a> {
+    ///Tweet is to be forwarded to subscribers of `typ`
+    Forward {
+        typ: TweetType,
+        ///Screen name of author
+        user: &'a str,
+        retweet: bool,
+    },
+    Reject(Reject),
+}
+
+#[inline]
+fn has_hashtag(text: &str, keyword: &str) -> bool {
+    text.match_indices(keyword).any(|(idx, _)| text[..idx].ends_with('#'))
+}
+
+///Decides what to do with tweet, using first matching rule.
+pub fn classify<'a>(tweet: &'a egg_mode::tweet::Tweet, rules: &[Rule]) -> Decision<'a> {
+    if tweet.retweeted_status.is_some() {
+        return Decision::Reject(Reject::Retweet);
+    } else if tweet.in_reply_to_status_id.is_some() {
+        return Decision::Reject(Reject::Reply);
+    }
+
+    let user = match tweet.user.as_ref() {
+        Some(user) => user,
+        None => return Decision::Reject(Reject::NoAuthor),
+    };
+
+    let mut reject = Reject::Unfiltered;
+
+    for rule in rules {
+        let is_match = match tweet.entities.hashtags.iter().any(|hash_tag| hash_tag.text.starts_with(rule.keyword)) {
+            true => true,
+            //tweet.entities.hashtags doesn't contain hashtags for long tweets
+            false => has_hashtag(&tweet.text, rule.keyword),
+        };
+
+        let is_match = match is_match {
+            true => true,
+            false => match tweet.text.contains(rule.keyword) {
+                true => match rule.trusted.binary_search(&user.id).is_ok() {
+                    true => true,
+                    false => {
+                        reject = Reject::Untrusted;
+                        false
+                    }
+                },
+                false => false,
+            }
+        };
+
+        if is_match {
+            return Decision::Forward {
+                typ: rule.typ,
+                user: &user.screen_name,
+                retweet: rule.retweet,
+            };
+        }
+    }
+
+    Decision::Reject(reject)
+}
+
+async fn send_tweet(http: &serenity::http::Http, id: u64, name: &str, ch_id: u64) {
     STATS.increment(stats::TwitterRetweet);
-    match serenity::model::id::ChannelId(ch_id).say(http, format_args!("https://twitter.com/{}/status/{}", name, id)) {
+    match discord::say(http, serenity::model::id::ChannelId::new(ch_id), format!("https://twitter.com/{}/status/{}", name, id)).await {
         Ok(_) => (),
-        Err(serenity::Error::Http(error)) => match *error {
-            serenity::prelude::HttpError::UnsuccessfulRequest(_) => {
-                STATS.increment(stats::DiscordMsgReject);
-            },
-            error => {
-                rogu::warn!("Twitter redirect failed with error: {}", error);
-                STATS.increment(stats::DiscordMsgFail);
-            },
+        Err(serenity::Error::Http(serenity::prelude::HttpError::UnsuccessfulRequest(_))) => {
+            STATS.increment(stats::DiscordMsgReject);
+        },
+        Err(serenity::Error::Http(error)) => {
+            log::warn!("Twitter redirect failed with error: {}", error);
+            STATS.increment(stats::DiscordMsgFail);
         },
         Err(error) => {
-            rogu::warn!("Twitter redirect failed with error: {}", error);
+            log::warn!("Twitter redirect failed with error: {}", error);
             STATS.increment(stats::DiscordMsgReject);
         },
     }
 }
 
-pub fn redirect_tweet(http: &serenity::http::client::Http, id: u64, name: String, typ: TweetType) {
-    match typ {
-        TweetType::NazeBoku => {
-            config::DISCORD.with_read(move |config| for ch in config.channels.naze.iter() {
-                send_tweet(&*http, id, &name, *ch);
-            })
-        },
+async fn redirect_tweet(http: &serenity::http::Http, id: u64, name: String, typ: TweetType) {
+    let channels = match typ {
+        TweetType::NazeBoku => config::DISCORD.with_read(|config| config.channels.naze.iter().cloned().collect::<Vec<_>>()),
+    };
+
+    for ch in channels {
+        send_tweet(http, id, &name, ch).await;
     }
 }
 
-fn place_tweet(id: u64, name: String, typ: TweetType) {
-    let http = match discord::HTTP.read().as_ref() {
-        Some(cache) => cache.http.clone(),
-        None => {
-            //Cache it for when discord re-connects
-            let _ = BUFFERED_TWEETS.enqueue((id, name, typ));
-            return;
-        },
-    };
+fn place_tweet(tweets: &TweetSender, id: u64, name: String, typ: TweetType) {
+    if tweets.try_send((id, name, typ)).is_err() {
+        log::warn!("Unable to forward tweet id={}: too many tweets are pending", id);
+    }
+}
 
-    redirect_tweet(&*http, id, name, typ);
+//Forwards tweets to discord, waiting for it to re-connect if necessary
+async fn forward(mut tweets: TweetReceiver) {
+    while let Some((id, name, typ)) = tweets.recv().await {
+        let http = discord::wait_http().await;
+        redirect_tweet(&http, id, name, typ).await;
+    }
 }
 
-async fn retweet(id: u64) {
-    match egg_mode::tweet::retweet(id, &TOKEN).await {
+async fn retweet(api: Api, id: u64) {
+    let params = egg_mode::raw::ParamList::new().add_param("tweet_mode", "extended");
+    let req = egg_mode::raw::request_post(&format!("{}/1.1/statuses/retweet/{}.json", api.rest, id), &TOKEN, Some(&params));
+
+    let start = std::time::Instant::now();
+    let res = egg_mode::raw::response_json::<egg_mode::tweet::Tweet>(req).await;
+    stats::LATENCY.twitter_retweet.record(start.elapsed());
+
+    match res {
         Ok(_) => (),
         Err(error) => {
-            rogu::warn!("Unable to retweet id={}. Error: {}", id, error);
+            log::warn!("Unable to retweet id={}. Error: {}", id, error);
         }
     }
 }
 
-async fn greet() {
-    match egg_mode::tweet::DraftTweet::new(constants::JEANNE_GREETING).send(&TOKEN).await {
+async fn greet(api: Api) {
+    let start = std::time::Instant::now();
+    let res = tweet(&api, constants::JEANNE_GREETING).await;
+    stats::LATENCY.twitter_tweet.record(start.elapsed());
+
+    match res {
         Ok(_) => (),
         Err(error) => {
-            rogu::warn!("Unable to greet on twitter. Error: {}", error);
+            log::warn!("Unable to greet on twitter. Error: {}", error);
         }
     }
 }
 
-async fn talk() {
-    let mut interval = async_timer::Interval::platform_new(core::time::Duration::from_secs(86400));
+async fn talk(api: Api) {
+    let period = core::time::Duration::from_secs(86400);
+    //First tick is after period, not immediately
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
     loop {
-        interval.as_mut().await;
-        match egg_mode::tweet::DraftTweet::new(constants::get_jeanne_phrase()).send(&TOKEN).await {
+        interval.tick().await;
+
+        let start = std::time::Instant::now();
+        let res = tweet(&api, constants::get_jeanne_phrase()).await;
+        stats::LATENCY.twitter_tweet.record(start.elapsed());
+
+        match res {
             Ok(_) => {
                 STATS.increment(stats::TwitterPeriodicTweet);
             },
             Err(error) => {
-                rogu::warn!("Unable to send phrase. Error: {}", error);
+                log::warn!("Unable to send phrase. Error: {}", error);
             }
         }
     }
 }
 
-#[tokio::main]
-pub async fn worker() {
+///Handles messages of single stream connection.
+///
+///Returns once stream is disconnected or fails.
+async fn handle_stream<S, R>(mut stream: S, tweets: &TweetSender, retweet: &mut R) where S: Stream<Item = Result<StreamMessage, egg_mode::error::Error>> + Unpin, R: FnMut(u64) {
     use futures_util::stream::StreamExt;
 
-    tokio::spawn(greet());
-    tokio::spawn(talk());
+    while let Some(Ok(msg)) = stream.next().await {
+        health::twitter_message();
 
+        match msg {
+            StreamMessage::Tweet(tweet) => {
+                log::debug!("Incoming tweet {:?}", tweet);
+
+                match classify(&tweet, RULES) {
+                    Decision::Forward { typ, user, retweet: is_retweet } => {
+                        place_tweet(tweets, tweet.id, user.to_owned(), typ);
+                        if is_retweet {
+                            retweet(tweet.id);
+                        }
+                    },
+                    Decision::Reject(Reject::Untrusted) => {
+                        STATS.increment(stats::TwitterUntrustedTweet);
+                    },
+                    Decision::Reject(Reject::Unfiltered) => {
+                        STATS.increment(stats::TwitterUnfilteredTweet);
+                    },
+                    Decision::Reject(_) => (),
+                }
+            },
+            StreamMessage::Disconnect(code, error) => {
+                log::warn!("Twitter disconnected. Code={}, Error={}", code, error);
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+///Reads stream, re-connecting using `connect` until it returns `None`.
+async fn run_stream<S, C, R>(mut connect: C, tweets: &TweetSender, mut retweet: R) where S: Stream<Item = Result<StreamMessage, egg_mode::error::Error>> + Unpin, C: FnMut() -> Option<S>, R: FnMut(u64) {
     loop {
-        rogu::info!("Twitter stream starting...");
+        log::info!("Twitter stream starting...");
         STATS.increment(stats::TwitterStartStream);
+        health::twitter_message();
 
-        let mut stream = create_twitter_stream();
+        let stream = match connect() {
+            Some(stream) => stream,
+            None => break,
+        };
 
-        'msg: while let Some(Ok(msg)) = stream.next().await {
-            match msg {
-                egg_mode::stream::StreamMessage::Tweet(tweet) => if tweet.retweeted_status.is_none() && tweet.in_reply_to_status_id.is_none() {
-                    rogu::debug!("Incoming tweet {:?}", tweet);
+        handle_stream(stream, tweets, &mut retweet).await;
+    }
+}
 
-                    let (user_id, user_name) = match tweet.user {
-                        Some(user) => (user.id, user.screen_name),
-                        None => continue,
-                    };
+///Runs twitter tasks against `api`.
+pub async fn worker(api: Api) {
+    let (tweets, tweets_rx) = tokio::sync::mpsc::channel(BUFFERED_TWEETS);
 
-                    for hash_tag in tweet.entities.hashtags {
-                        if hash_tag.text.starts_with("なぜ僕") {
-                            place_tweet(tweet.id, user_name, TweetType::NazeBoku);
-                            tokio::spawn(retweet(tweet.id));
-                            continue 'msg;
-                        }
-                    }
+    tokio::spawn(forward(tweets_rx));
+    tokio::spawn(greet(api.clone()));
+    tokio::spawn(talk(api.clone()));
 
-                    //tweet.entities.hashtags doesn't contain hashtags for long tweets
-                    if tweet.text.contains("#なぜ僕") {
-                        place_tweet(tweet.id, user_name, TweetType::NazeBoku);
-                        tokio::spawn(retweet(tweet.id));
-                        continue;
-                    } else if tweet.text.contains("なぜ僕") {
-                        if TRUST_USER_IDS.binary_search(&user_id).is_ok() {
-                            place_tweet(tweet.id, user_name, TweetType::NazeBoku);
-                            tokio::spawn(retweet(tweet.id));
-                        } else {
-                            STATS.increment(stats::TwitterUntrustedTweet);
-                        }
+    run_stream(|| Some(create_twitter_stream(&api)), &tweets, |id| {
+        tokio::spawn(retweet(api.clone(), id));
+    }).await
+}
 
-                        continue;
-                    } else {
-                        STATS.increment(stats::TwitterUnfilteredTweet);
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::str::FromStr;
+
+    const STREAM_DISCONNECT: &str = include_str!("../tests/data/twitter/stream_disconnect.txt");
+    const STREAM_ERROR: &str = include_str!("../tests/data/twitter/stream_error.txt");
+
+    type Replay = futures_util::stream::Iter<std::vec::IntoIter<Result<StreamMessage, egg_mode::error::Error>>>;
+
+    ///Replays recorded stream payload, delimited the same way as twitter does.
+    fn replay(payload: &str) -> Replay {
+        let msgs = payload.split("\r\n").map(StreamMessage::from_str).collect::<Vec<_>>();
+        futures_util::stream::iter(msgs)
+    }
+
+    fn run(sessions: &[&str]) -> (Vec<(u64, String)>, Vec<u64>, usize) {
+        let rt = tokio::runtime::Builder::new_current_thread().build().expect("To create runtime");
+        let (tweets, mut tweets_rx) = tokio::sync::mpsc::channel(BUFFERED_TWEETS);
+
+        let mut sessions = sessions.iter();
+        let mut connects = 0;
+        let mut retweets = Vec::new();
+
+        rt.block_on(run_stream(|| {
+            connects += 1;
+            sessions.next().map(|payload| replay(payload))
+        }, &tweets, |id| retweets.push(id)));
+
+        let mut forwarded = Vec::new();
+        while let Ok((id, name, TweetType::NazeBoku)) = tweets_rx.try_recv() {
+            forwarded.push((id, name));
+        }
+
+        (forwarded, retweets, connects)
+    }
+
+    ///Fake twitter, serving recorded stream sessions in order and accepting retweets.
+    struct FakeTwitter {
+        api: Api,
+        requests: tokio::sync::mpsc::UnboundedReceiver<(String, String)>,
+    }
+
+    async fn serve_fake(stream: tokio::net::TcpStream, sessions: std::sync::Arc<parking_lot::Mutex<std::vec::IntoIter<&'static str>>>, requests: tokio::sync::mpsc::UnboundedSender<(String, String)>) -> std::io::Result<()> {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::io::BufReader::new(stream);
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+
+        let mut parts = line.split_whitespace();
+        let path = parts.nth(1).unwrap_or("").to_owned();
+
+        let mut len = 0;
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            match line.trim().split_once(':') {
+                Some((name, value)) if name.eq_ignore_ascii_case("content-length") => len = value.trim().parse().unwrap_or(0),
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await?;
+        let _ = requests.send((path.clone(), String::from_utf8_lossy(&body).into_owned()));
+
+        let stream = stream.get_mut();
+        if path == "/1.1/statuses/filter.json" {
+            let session = sessions.lock().next().unwrap_or("");
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n").await?;
+
+            //Each message is sent in its own chunk, as twitter does
+            for msg in session.split("\r\n") {
+                let msg = format!("{}\r\n", msg);
+                stream.write_all(format!("{:x}\r\n{}\r\n", msg.len(), msg).as_bytes()).await?;
+                stream.flush().await?;
+            }
+            stream.write_all(b"0\r\n\r\n").await?;
+        } else if path.starts_with("/1.1/statuses/retweet/") {
+            let tweet = STREAM_DISCONNECT.split("\r\n").nth(1).expect("To have tweet");
+            stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", tweet.len(), tweet).as_bytes()).await?;
+        } else {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+        }
+
+        stream.flush().await
+    }
+
+    impl FakeTwitter {
+        async fn start(sessions: Vec<&'static str>) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("To bind fake twitter");
+            let url = format!("http://{}", listener.local_addr().expect("Fake twitter address"));
+            let sessions = std::sync::Arc::new(parking_lot::Mutex::new(sessions.into_iter()));
+            let (requests_tx, requests) = tokio::sync::mpsc::unbounded_channel();
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve_fake(stream, sessions.clone(), requests_tx.clone()));
+                }
+            });
+
+            Self {
+                api: Api {
+                    rest: url.clone(),
+                    stream: url,
                 },
-                egg_mode::stream::StreamMessage::Disconnect(code, error) => {
-                    rogu::warn!("Twitter disconnected. Code={}, Error={}", code, error);
-                    break;
+                requests,
+            }
+        }
+
+        ///Waits for `num` retweets, returning their sorted ids.
+        async fn retweets(&mut self, num: usize) -> Vec<u64> {
+            let wait = async {
+                let mut ids = Vec::new();
+                while ids.len() < num {
+                    let (path, _) = self.requests.recv().await.expect("Fake twitter to run");
+                    if let Some(id) = path.strip_prefix("/1.1/statuses/retweet/").and_then(|id| id.strip_suffix(".json")) {
+                        ids.push(id.parse().expect("To have retweet id"));
+                    }
+                }
+                ids
+            };
+
+            let mut ids = tokio::time::timeout(core::time::Duration::from_secs(10), wait).await.expect("To retweet");
+            ids.sort_unstable();
+            ids
+        }
+    }
+
+    #[test]
+    fn should_filter_retweet_and_reconnect_against_fake_twitter() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("To create runtime");
+
+        rt.block_on(async {
+            let mut twitter = FakeTwitter::start(vec![STREAM_ERROR, STREAM_DISCONNECT]).await;
+            let api = twitter.api.clone();
+            let (tweets, mut tweets_rx) = tokio::sync::mpsc::channel(BUFFERED_TWEETS);
+
+            let mut connects = 0;
+            run_stream(|| {
+                connects += 1;
+                match connects {
+                    1 | 2 => Some(create_twitter_stream(&api)),
+                    _ => None,
                 }
-                _ => (),
+            }, &tweets, |id| {
+                tokio::spawn(retweet(api.clone(), id));
+            }).await;
+            assert_eq!(connects, 3);
+
+            let mut forwarded = Vec::new();
+            while let Ok((id, name, TweetType::NazeBoku)) = tweets_rx.try_recv() {
+                forwarded.push((id, name));
+            }
+            assert_eq!(forwarded, [(8, "naze_fan".to_owned()), (1, "naze_fan".to_owned()), (5, "sazanek".to_owned())]);
+
+            let (path, body) = twitter.requests.recv().await.expect("To connect stream");
+            assert_eq!(path, "/1.1/statuses/filter.json");
+            assert!(body.contains("filter_level=none"), "Unexpected filter {}", body);
+            assert!(body.contains("track="), "Unexpected filter {}", body);
+
+            assert_eq!(twitter.retweets(3).await, [1, 5, 8]);
+        });
+    }
+
+    struct Case {
+        name: &'static str,
+        user: Option<(u64, &'static str)>,
+        text: &'static str,
+        hashtags: &'static [&'static str],
+        is_reply: bool,
+        is_retweet: bool,
+        expected: Decision<'static>,
+    }
+
+    const FAN: Option<(u64, &str)> = Some((100, "naze_fan"));
+    const TRUSTED: Option<(u64, &str)> = Some((2325188503, "sazanek"));
+
+    const FORWARD_FAN: Decision = Decision::Forward { typ: TweetType::NazeBoku, user: "naze_fan", retweet: true };
+    const FORWARD_TRUSTED: Decision = Decision::Forward { typ: TweetType::NazeBoku, user: "sazanek", retweet: true };
+
+    const CASES: &[Case] = &[
+        Case { name: "hashtag", user: FAN, text: "読んだ #なぜ僕", hashtags: &["なぜ僕"], is_reply: false, is_retweet: false, expected: FORWARD_FAN },
+        Case { name: "hashtag with suffix", user: FAN, text: "読んだ #なぜ僕の世界", hashtags: &["なぜ僕の世界"], is_reply: false, is_retweet: false, expected: FORWARD_FAN },
+        Case { name: "long tweet without hashtag entities", user: FAN, text: "長いツイート… #なぜ僕", hashtags: &[], is_reply: false, is_retweet: false, expected: FORWARD_FAN },
+        Case { name: "trusted author without hashtag", user: TRUSTED, text: "なぜ僕 更新しました", hashtags: &[], is_reply: false, is_retweet: false, expected: FORWARD_TRUSTED },
+        Case { name: "untrusted author without hashtag", user: FAN, text: "なぜ僕の世界", hashtags: &[], is_reply: false, is_retweet: false, expected: Decision::Reject(Reject::Untrusted) },
+        Case { name: "keyword after other hashtag", user: FAN, text: "#漫画 なぜ僕", hashtags: &["漫画"], is_reply: false, is_retweet: false, expected: Decision::Reject(Reject::Untrusted) },
+        Case { name: "unrelated", user: TRUSTED, text: "今日は晴れ", hashtags: &[], is_reply: false, is_retweet: false, expected: Decision::Reject(Reject::Unfiltered) },
+        Case { name: "other hashtag", user: FAN, text: "#漫画", hashtags: &["漫画"], is_reply: false, is_retweet: false, expected: Decision::Reject(Reject::Unfiltered) },
+        Case { name: "reply", user: TRUSTED, text: "@naze_fan #なぜ僕", hashtags: &["なぜ僕"], is_reply: true, is_retweet: false, expected: Decision::Reject(Reject::Reply) },
+        Case { name: "retweet", user: FAN, text: "RT @sazanek: #なぜ僕", hashtags: &["なぜ僕"], is_reply: false, is_retweet: true, expected: Decision::Reject(Reject::Retweet) },
+        Case { name: "retweet of reply", user: FAN, text: "RT @sazanek: #なぜ僕", hashtags: &["なぜ僕"], is_reply: true, is_retweet: true, expected: Decision::Reject(Reject::Retweet) },
+        Case { name: "no author", user: None, text: "#なぜ僕", hashtags: &["なぜ僕"], is_reply: false, is_retweet: false, expected: Decision::Reject(Reject::NoAuthor) },
+    ];
+
+    ///Builds tweet out of recorded one, replacing fields relevant to classification.
+    fn tweet(case: &Case) -> egg_mode::tweet::Tweet {
+        let mut tweet: serde_json::Value = serde_json::from_str(STREAM_DISCONNECT.split("\r\n").nth(1).expect("To have tweet")).expect("To parse tweet");
+
+        let hashtags = case.hashtags.iter().map(|hash_tag| {
+            let start = case.text[..case.text.find(hash_tag).expect("Hashtag in text")].chars().count() - 1;
+            serde_json::json!({ "text": hash_tag, "indices": [start, start + hash_tag.chars().count() + 1] })
+        }).collect::<Vec<_>>();
+
+        if case.is_retweet {
+            tweet["retweeted_status"] = tweet.clone();
+        }
+        tweet["text"] = case.text.into();
+        tweet["entities"]["hashtags"] = hashtags.into();
+        if case.is_reply {
+            tweet["in_reply_to_status_id"] = 10.into();
+        }
+        match case.user {
+            Some((id, name)) => {
+                tweet["user"]["id"] = id.into();
+                tweet["user"]["screen_name"] = name.into();
+            },
+            None => tweet["user"] = serde_json::Value::Null,
+        }
+
+        serde_json::from_value(tweet).expect("To build tweet")
+    }
+
+    #[test]
+    fn should_classify_tweets() {
+        for case in CASES {
+            let tweet = tweet(case);
+            assert_eq!(classify(&tweet, RULES), case.expected, "Case '{}'", case.name);
+        }
+    }
+
+    #[test]
+    fn should_use_first_matching_rule() {
+        const OTHER_RULES: &[Rule] = &[
+            Rule { typ: TweetType::NazeBoku, keyword: "漫画", trusted: &[], retweet: false },
+            Rule { typ: TweetType::NazeBoku, keyword: "なぜ僕", trusted: &[], retweet: true },
+        ];
+
+        let case = Case { name: "untrusted keyword before hashtag", user: FAN, text: "漫画 #なぜ僕", hashtags: &["なぜ僕"], is_reply: false, is_retweet: false, expected: FORWARD_FAN };
+        assert_eq!(classify(&tweet(&case), OTHER_RULES), case.expected);
+
+        let case = Case { name: "both hashtags", user: FAN, text: "#漫画 #なぜ僕", hashtags: &["漫画", "なぜ僕"], is_reply: false, is_retweet: false, expected: Decision::Forward { typ: TweetType::NazeBoku, user: "naze_fan", retweet: false } };
+        assert_eq!(classify(&tweet(&case), OTHER_RULES), case.expected);
+    }
+
+    #[test]
+    fn should_parse_recorded_payloads() {
+        let mut tweets = 0;
+
+        for msg in STREAM_DISCONNECT.split("\r\n") {
+            match StreamMessage::from_str(msg).expect("To parse message") {
+                StreamMessage::Tweet(_) => tweets += 1,
+                StreamMessage::Ping | StreamMessage::Disconnect(..) => (),
+                msg => panic!("Unexpected message: {:?}", msg),
             }
         }
+
+        assert_eq!(tweets, 7);
+    }
+
+    #[test]
+    fn should_forward_and_retweet_matching_tweets_until_disconnect() {
+        let (forwarded, retweets, connects) = run(&[STREAM_DISCONNECT]);
+
+        assert_eq!(forwarded, [(1, "naze_fan".to_owned()), (5, "sazanek".to_owned())]);
+        assert_eq!(retweets, [1, 5]);
+        assert_eq!(connects, 2);
+    }
+
+    #[test]
+    fn should_reconnect_after_stream_error() {
+        let (forwarded, retweets, connects) = run(&[STREAM_ERROR, STREAM_DISCONNECT]);
+
+        assert_eq!(forwarded, [(8, "naze_fan".to_owned()), (1, "naze_fan".to_owned()), (5, "sazanek".to_owned())]);
+        assert_eq!(retweets, [8, 1, 5]);
+        assert_eq!(connects, 3);
+    }
+
+    #[test]
+    fn should_drop_tweets_when_forwarding_is_stuck() {
+        let payload = (0..BUFFERED_TWEETS + 1).map(|_| STREAM_DISCONNECT.split("\r\n").nth(1).expect("To have tweet"))
+                                              .collect::<Vec<_>>()
+                                              .join("\r\n");
+        let (forwarded, retweets, _) = run(&[&payload]);
+
+        assert_eq!(forwarded.len(), BUFFERED_TWEETS);
+        assert_eq!(retweets.len(), BUFFERED_TWEETS + 1);
     }
 }
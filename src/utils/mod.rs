@@ -19,7 +19,3 @@ macro_rules! unreach {
         unreachable!()
     })
 }
-
-pub mod four {
-    statiki::declare_vec!(4);
-}
@@ -1,96 +1,210 @@
 //Took from heapless
+//
+//Model tests are run with `RUSTFLAGS="--cfg mpmc_loom" cargo test --release --test mpmc_loom`,
+//in which case atomics are provided by loom within test crate only.
+//Unit tests are also run under `cargo +nightly miri test mpmc` by CI.
 
-use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
-use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(not(all(test, mpmc_loom)))]
+use core::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(all(test, mpmc_loom)))]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(all(test, mpmc_loom))]
+use loom::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+#[cfg(all(test, mpmc_loom))]
+use loom::sync::{Mutex, MutexGuard};
+#[cfg(all(test, mpmc_loom))]
+use loom::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{self, Poll};
+use std::thread;
 
-/// MPMC queue with a capacity for 64 elements
-pub struct Q64<T> {
-    buffer: UnsafeCell<[Cell<T>; 64]>,
-    dequeue_pos: AtomicU8,
-    enqueue_pos: AtomicU8,
+//Mirrors API of loom's `UnsafeCell`, so that model can track access to data.
+#[cfg(not(all(test, mpmc_loom)))]
+struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(all(test, mpmc_loom)))]
+impl<T> UnsafeCell<T> {
+    const fn new(data: T) -> Self {
+        Self(core::cell::UnsafeCell::new(data))
+    }
+
+    #[inline(always)]
+    fn with<R>(&self, cb: impl FnOnce(*const T) -> R) -> R {
+        cb(self.0.get())
+    }
+
+    #[inline(always)]
+    fn with_mut<R>(&self, cb: impl FnOnce(*mut T) -> R) -> R {
+        cb(self.0.get())
+    }
+}
+
+enum Waiter {
+    Task(task::Waker),
+    Thread(thread::Thread),
+}
+
+impl Waiter {
+    fn will_wake(&self, other: &Waiter) -> bool {
+        match (self, other) {
+            (Waiter::Task(waker), Waiter::Task(other)) => waker.will_wake(other),
+            (Waiter::Thread(thread), Waiter::Thread(other)) => thread.id() == other.id(),
+            _ => false,
+        }
+    }
+
+    fn wake(self) {
+        match self {
+            Waiter::Task(waker) => waker.wake(),
+            Waiter::Thread(thread) => thread.unpark(),
+        }
+    }
 }
 
-impl<T> Q64<T> {
-    const MASK: u8 = 64 - 1;
+//Registered waiters, identified by key of their registration
+struct Waiters {
+    list: Vec<(usize, Waiter)>,
+    next_key: usize,
+}
+
+impl Waiters {
+    const fn new() -> Self {
+        Self {
+            list: Vec::new(),
+            next_key: 0,
+        }
+    }
+}
+
+/// MPMC queue with a capacity for `N` elements
+///
+/// `N` must be power of two and greater than 1
+pub struct Queue<T, const N: usize> {
+    buffer: [Cell<T>; N],
+    dequeue_pos: AtomicUsize,
+    enqueue_pos: AtomicUsize,
+    //Consumers waiting for new element, only locked when there are waiters
+    waiters: Mutex<Waiters>,
+    has_waiters: AtomicBool,
+}
+
+/// MPMC queue with a capacity for 64 elements
+pub type Q64<T> = Queue<T, 64>;
+
+impl<T, const N: usize> Queue<T, N> {
+    const MASK: usize = N - 1;
+    const CAPACITY_CHECK: () = assert!(N > 1 && N.is_power_of_two(), "Queue capacity must be power of two and greater than 1");
 
     /// Creates an empty queue
+    #[cfg(not(all(test, mpmc_loom)))]
     pub const fn new() -> Self {
+        let () = Self::CAPACITY_CHECK;
+
         Self {
-            buffer: UnsafeCell::new([
-                Cell::new(0),
-                Cell::new(1),
-                Cell::new(2),
-                Cell::new(3),
-                Cell::new(4),
-                Cell::new(5),
-                Cell::new(6),
-                Cell::new(7),
-                Cell::new(8),
-                Cell::new(9),
-                Cell::new(10),
-                Cell::new(11),
-                Cell::new(12),
-                Cell::new(13),
-                Cell::new(14),
-                Cell::new(15),
-                Cell::new(16),
-                Cell::new(17),
-                Cell::new(18),
-                Cell::new(19),
-                Cell::new(20),
-                Cell::new(21),
-                Cell::new(22),
-                Cell::new(23),
-                Cell::new(24),
-                Cell::new(25),
-                Cell::new(26),
-                Cell::new(27),
-                Cell::new(28),
-                Cell::new(29),
-                Cell::new(30),
-                Cell::new(31),
-                Cell::new(32),
-                Cell::new(33),
-                Cell::new(34),
-                Cell::new(35),
-                Cell::new(36),
-                Cell::new(37),
-                Cell::new(38),
-                Cell::new(39),
-                Cell::new(40),
-                Cell::new(41),
-                Cell::new(42),
-                Cell::new(43),
-                Cell::new(44),
-                Cell::new(45),
-                Cell::new(46),
-                Cell::new(47),
-                Cell::new(48),
-                Cell::new(49),
-                Cell::new(50),
-                Cell::new(51),
-                Cell::new(52),
-                Cell::new(53),
-                Cell::new(54),
-                Cell::new(55),
-                Cell::new(56),
-                Cell::new(57),
-                Cell::new(58),
-                Cell::new(59),
-                Cell::new(60),
-                Cell::new(61),
-                Cell::new(62),
-                Cell::new(63),
-            ]),
-            dequeue_pos: AtomicU8::new(0),
-            enqueue_pos: AtomicU8::new(0),
+            buffer: [const { Cell::new() }; N],
+            dequeue_pos: AtomicUsize::new(0),
+            enqueue_pos: AtomicUsize::new(0),
+            waiters: Mutex::new(Waiters::new()),
+            has_waiters: AtomicBool::new(false),
+        }
+    }
+
+    /// Creates an empty queue
+    #[cfg(all(test, mpmc_loom))]
+    pub fn new() -> Self {
+        let () = Self::CAPACITY_CHECK;
+
+        Self {
+            buffer: core::array::from_fn(|_| Cell::new()),
+            dequeue_pos: AtomicUsize::new(0),
+            enqueue_pos: AtomicUsize::new(0),
+            waiters: Mutex::new(Waiters::new()),
+            has_waiters: AtomicBool::new(false),
         }
     }
 
     /// Returns the item in the front of the queue, or `None` if the queue is empty
     pub fn dequeue(&self) -> Option<T> {
-        unsafe { dequeue(self.buffer.get() as *mut _, &self.dequeue_pos, Self::MASK) }
+        unsafe { dequeue(self.buffer.as_ptr(), &self.dequeue_pos, Self::MASK) }
+    }
+
+    /// Returns maximum number of elements
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns approximate number of elements in the queue
+    ///
+    /// Result may be outdated when queue is used concurrently
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        let dequeue_pos = self.dequeue_pos.load(Ordering::Relaxed);
+        let enqueue_pos = self.enqueue_pos.load(Ordering::Relaxed);
+
+        //Consumer may move ahead of loaded enqueue position
+        match enqueue_pos.wrapping_sub(dequeue_pos) as isize {
+            len if len < 0 => 0,
+            len => core::cmp::min(len as usize, self.capacity()),
+        }
+    }
+
+    /// Returns whether queue appears empty
+    ///
+    /// Result may be outdated when queue is used concurrently
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns iterator over elements, that stops once queue is empty
+    pub fn try_iter(&self) -> TryIter<'_, T, N> {
+        TryIter {
+            queue: self,
+        }
+    }
+
+    /// Removes all elements from the queue, returning them as iterator
+    ///
+    /// Elements not consumed by iterator are dropped together with it
+    #[allow(unused)]
+    pub fn drain(&mut self) -> Drain<'_, T, N> {
+        Drain {
+            queue: self,
+        }
+    }
+
+    /// Returns future that resolves into the item in the front of the queue, once there is any
+    pub fn dequeue_async(&self) -> Dequeue<'_, T, N> {
+        Dequeue {
+            queue: self,
+            key: None,
+        }
+    }
+
+    /// Returns the item in the front of the queue, parking current thread until there is any
+    #[allow(unused)]
+    pub fn dequeue_blocking(&self) -> T {
+        let mut key = None;
+
+        loop {
+            if let Some(item) = self.dequeue() {
+                self.deregister(&mut key);
+                return item;
+            }
+
+            self.register(&mut key, Waiter::Thread(thread::current()));
+
+            //Element could be added before registration
+            match self.dequeue() {
+                Some(item) => {
+                    self.deregister(&mut key);
+                    return item;
+                },
+                None => thread::park(),
+            }
+        }
     }
 
     /// Adds an `item` to the end of the queue
@@ -99,43 +213,210 @@ impl<T> Q64<T> {
     pub fn enqueue(&self, item: T) -> Result<(), T> {
         unsafe {
             enqueue(
-                self.buffer.get() as *mut _,
+                self.buffer.as_ptr(),
                 &self.enqueue_pos,
                 Self::MASK,
                 item,
             )
+        }?;
+
+        //Pairs with fence in `register`, so that either consumer sees new element or we see waiter.
+        atomic::fence(Ordering::SeqCst);
+        if self.has_waiters.load(Ordering::Relaxed) {
+            self.wake_all();
+        }
+
+        Ok(())
+    }
+
+    fn lock_waiters(&self) -> MutexGuard<'_, Waiters> {
+        match self.waiters.lock() {
+            Ok(waiters) => waiters,
+            Err(error) => error.into_inner(),
+        }
+    }
+
+    //Registers `waiter` under `key`, replacing previous one unless it would wake the same consumer.
+    //
+    //Registration is removed once woken, in which case new `key` is assigned.
+    fn register(&self, key: &mut Option<usize>, waiter: Waiter) {
+        let mut waiters = self.lock_waiters();
+        let registered = key.and_then(|key| waiters.list.iter_mut().find(|(waiter_key, _)| *waiter_key == key));
+
+        match registered {
+            Some((_, registered)) => if !registered.will_wake(&waiter) {
+                *registered = waiter;
+            },
+            None => {
+                let new_key = waiters.next_key;
+                waiters.next_key = new_key.wrapping_add(1);
+                waiters.list.push((new_key, waiter));
+                *key = Some(new_key);
+            }
+        }
+
+        self.has_waiters.store(true, Ordering::Relaxed);
+        drop(waiters);
+
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    //Removes registration under `key`, if it has not been woken yet.
+    fn deregister(&self, key: &mut Option<usize>) {
+        let key = match key.take() {
+            Some(key) => key,
+            None => return,
+        };
+
+        let mut waiters = self.lock_waiters();
+        waiters.list.retain(|(waiter_key, _)| *waiter_key != key);
+        if waiters.list.is_empty() {
+            self.has_waiters.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn wake_all(&self) {
+        let waiters = {
+            let mut waiters = self.lock_waiters();
+            self.has_waiters.store(false, Ordering::Relaxed);
+            core::mem::take(&mut waiters.list)
+        };
+
+        //Wake everyone as we cannot know which consumer is still waiting
+        for (_, waiter) in waiters {
+            waiter.wake();
         }
     }
 }
 
-unsafe impl<T> Sync for Q64<T> where T: Send {}
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {
+        }
+    }
+}
+
+/// Iterator returned by `Queue::try_iter`
+pub struct TryIter<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
 
+impl<T, const N: usize> Iterator for TryIter<'_, T, N> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.dequeue()
+    }
+}
+
+/// Iterator returned by `Queue::drain`
+pub struct Drain<'a, T, const N: usize> {
+    queue: &'a mut Queue<T, N>,
+}
+
+impl<T, const N: usize> Iterator for Drain<'_, T, N> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.dequeue()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.queue.len();
+        (len, Some(len))
+    }
+}
+
+impl<T, const N: usize> Drop for Drain<'_, T, N> {
+    fn drop(&mut self) {
+        for _ in self {
+        }
+    }
+}
+
+/// Future returned by `Queue::dequeue_async`
+pub struct Dequeue<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+    //Key of waker registration, if any
+    key: Option<usize>,
+}
+
+impl<T, const N: usize> Future for Dequeue<'_, T, N> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if let Some(item) = this.queue.dequeue() {
+            this.queue.deregister(&mut this.key);
+            return Poll::Ready(item);
+        }
+
+        this.queue.register(&mut this.key, Waiter::Task(ctx.waker().clone()));
+
+        //Element could be added before registration
+        match this.queue.dequeue() {
+            Some(item) => {
+                this.queue.deregister(&mut this.key);
+                Poll::Ready(item)
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Dequeue<'_, T, N> {
+    fn drop(&mut self) {
+        self.queue.deregister(&mut self.key);
+    }
+}
+
+unsafe impl<T, const N: usize> Sync for Queue<T, N> where T: Send {}
+
+//Sequence is stored relative to cell's index,
+//so that all cells can be created with the same initial value.
 struct Cell<T> {
-    data: MaybeUninit<T>,
-    sequence: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+    sequence: AtomicUsize,
 }
 
 impl<T> Cell<T> {
-    const fn new(seq: u8) -> Self {
+    #[cfg(not(all(test, mpmc_loom)))]
+    const fn new() -> Self {
+        Self {
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(all(test, mpmc_loom))]
+    fn new() -> Self {
         Self {
-            data: MaybeUninit::uninit(),
-            sequence: AtomicU8::new(seq),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            sequence: AtomicUsize::new(0),
         }
     }
 }
 
-unsafe fn dequeue<T>(buffer: *mut Cell<T>, dequeue_pos: &AtomicU8, mask: u8) -> Option<T> {
+unsafe fn dequeue<T>(buffer: *const Cell<T>, dequeue_pos: &AtomicUsize, mask: usize) -> Option<T> {
     let mut pos = dequeue_pos.load(Ordering::Relaxed);
 
+    let mut idx;
     let mut cell;
     loop {
-        cell = buffer.add(usize::from(pos & mask));
-        let seq = (*cell).sequence.load(Ordering::Acquire);
-        let dif = i16::from(seq) - i16::from(pos.wrapping_add(1));
+        idx = pos & mask;
+        cell = buffer.add(idx);
+        let seq = (*cell).sequence.load(Ordering::Acquire).wrapping_add(idx);
+        let dif = seq.wrapping_sub(pos.wrapping_add(1)) as isize;
 
         if dif == 0 {
-            if dequeue_pos.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed).is_ok() {
-                break;
+            match dequeue_pos.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                //Retry from position that another thread has moved to
+                Err(actual) => pos = actual,
             }
         } else if dif < 0 {
             return None;
@@ -144,23 +425,27 @@ unsafe fn dequeue<T>(buffer: *mut Cell<T>, dequeue_pos: &AtomicU8, mask: u8) ->
         }
     }
 
-    let data = (*cell).data.as_ptr().read();
-    (*cell).sequence.store(pos.wrapping_add(mask).wrapping_add(1), Ordering::Release);
+    let data = (*cell).data.with(|data| data.read().assume_init());
+    (*cell).sequence.store(pos.wrapping_add(mask).wrapping_add(1).wrapping_sub(idx), Ordering::Release);
     Some(data)
 }
 
-unsafe fn enqueue<T>(buffer: *mut Cell<T>, enqueue_pos: &AtomicU8, mask: u8, item: T) -> Result<(), T> {
+unsafe fn enqueue<T>(buffer: *const Cell<T>, enqueue_pos: &AtomicUsize, mask: usize, item: T) -> Result<(), T> {
     let mut pos = enqueue_pos.load(Ordering::Relaxed);
 
+    let mut idx;
     let mut cell;
     loop {
-        cell = buffer.add(usize::from(pos & mask));
-        let seq = (*cell).sequence.load(Ordering::Acquire);
-        let dif = i16::from(seq) - i16::from(pos);
+        idx = pos & mask;
+        cell = buffer.add(idx);
+        let seq = (*cell).sequence.load(Ordering::Acquire).wrapping_add(idx);
+        let dif = seq.wrapping_sub(pos) as isize;
 
         if dif == 0 {
-            if enqueue_pos.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed).is_ok() {
-                break;
+            match enqueue_pos.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                //Retry from position that another thread has moved to
+                Err(actual) => pos = actual,
             }
         } else if dif < 0 {
             return Err(item);
@@ -169,7 +454,291 @@ unsafe fn enqueue<T>(buffer: *mut Cell<T>, enqueue_pos: &AtomicU8, mask: u8, ite
         }
     }
 
-    (*cell).data.as_mut_ptr().write(item);
-    (*cell).sequence.store(pos.wrapping_add(1), Ordering::Release);
+    (*cell).data.with_mut(|data| data.write(MaybeUninit::new(item)));
+    (*cell).sequence.store(pos.wrapping_add(1).wrapping_sub(idx), Ordering::Release);
     Ok(())
 }
+
+#[cfg(all(test, not(mpmc_loom)))]
+mod tests {
+    use super::Queue;
+
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{Context, Poll, Waker};
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread;
+
+    struct CountWake(AtomicUsize);
+
+    impl Wake for CountWake {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    #[cfg(not(miri))]
+    const STRESS_ITEMS: usize = 100_000;
+    #[cfg(miri)]
+    const STRESS_ITEMS: usize = 200;
+
+    //Creates queue as if `pos` elements passed through it
+    fn with_position<T, const N: usize>(pos: usize) -> Queue<T, N> {
+        let queue = Queue::new();
+
+        queue.enqueue_pos.store(pos, Ordering::Relaxed);
+        queue.dequeue_pos.store(pos, Ordering::Relaxed);
+        for offset in 0..N {
+            let next_pos = pos.wrapping_add(offset);
+            let idx = next_pos & Queue::<T, N>::MASK;
+            queue.buffer[idx].sequence.store(next_pos.wrapping_sub(idx), Ordering::Relaxed);
+        }
+
+        queue
+    }
+
+    fn check_fill_and_empty<const N: usize>(queue: &Queue<usize, N>, rounds: usize) {
+        for round in 0..rounds {
+            assert!(queue.is_empty());
+            assert!(queue.dequeue().is_none());
+
+            for idx in 0..N {
+                assert!(queue.enqueue(round + idx).is_ok());
+                assert_eq!(queue.len(), idx + 1);
+            }
+
+            assert_eq!(queue.enqueue(usize::MAX), Err(usize::MAX));
+            assert_eq!(queue.len(), N);
+
+            for idx in 0..N {
+                assert_eq!(queue.dequeue(), Some(round + idx));
+            }
+        }
+
+        assert!(queue.dequeue().is_none());
+    }
+
+    #[test]
+    fn should_respect_full_and_empty_boundaries() {
+        let queue = Queue::<usize, 4>::new();
+        assert_eq!(queue.capacity(), 4);
+        check_fill_and_empty(&queue, 3);
+
+        let queue = Queue::<usize, 64>::new();
+        check_fill_and_empty(&queue, 3);
+    }
+
+    #[test]
+    fn should_wrap_around_positions() {
+        let queue = with_position::<usize, 4>(usize::MAX - 5);
+        check_fill_and_empty(&queue, 5);
+
+        let queue = with_position::<usize, 2>(usize::MAX);
+        check_fill_and_empty(&queue, 3);
+    }
+
+    #[test]
+    fn should_interleave_across_wrap_around() {
+        let queue = with_position::<usize, 4>(usize::MAX - 1);
+
+        for idx in 0..16 {
+            assert!(queue.enqueue(idx).is_ok());
+            assert!(queue.enqueue(idx + 100).is_ok());
+            assert_eq!(queue.dequeue(), Some(idx));
+            assert_eq!(queue.dequeue(), Some(idx + 100));
+        }
+
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn should_drop_remaining_items() {
+        let item = Arc::new(());
+
+        let mut queue = Queue::<Arc<()>, 8>::new();
+        for _ in 0..5 {
+            assert!(queue.enqueue(item.clone()).is_ok());
+        }
+        assert_eq!(Arc::strong_count(&item), 6);
+
+        {
+            let mut drain = queue.drain();
+            assert_eq!(drain.size_hint(), (5, Some(5)));
+            assert!(drain.next().is_some());
+        }
+        assert!(queue.is_empty());
+        assert_eq!(Arc::strong_count(&item), 1);
+
+        for _ in 0..3 {
+            assert!(queue.enqueue(item.clone()).is_ok());
+        }
+        assert_eq!(queue.try_iter().take(1).count(), 1);
+        assert_eq!(Arc::strong_count(&item), 3);
+
+        drop(queue);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn should_wake_blocking_consumer() {
+        let queue = Arc::new(Queue::<usize, 4>::new());
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || (0..16).map(|_| queue.dequeue_blocking()).sum::<usize>())
+        };
+
+        for idx in 0..16 {
+            while queue.enqueue(idx).is_err() {
+                thread::yield_now();
+            }
+        }
+
+        assert_eq!(consumer.join().expect("Consumer to finish"), (0..16).sum::<usize>());
+    }
+
+    #[test]
+    //Tokio's runtime relies on IO driver, which is not supported by miri
+    #[cfg_attr(miri, ignore)]
+    fn should_wake_async_consumer() {
+        let queue = Arc::new(Queue::<usize, 4>::new());
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread().build().expect("To create runtime");
+                rt.block_on(async move {
+                    let mut sum = 0;
+                    for _ in 0..16 {
+                        sum += queue.dequeue_async().await;
+                    }
+                    sum
+                })
+            })
+        };
+
+        for idx in 0..16 {
+            while queue.enqueue(idx).is_err() {
+                thread::yield_now();
+            }
+        }
+
+        assert_eq!(consumer.join().expect("Consumer to finish"), (0..16).sum::<usize>());
+    }
+
+    #[test]
+    fn should_keep_single_registration_per_consumer() {
+        let queue = Queue::<usize, 4>::new();
+        let first = Arc::new(CountWake(AtomicUsize::new(0)));
+        let second = Arc::new(CountWake(AtomicUsize::new(0)));
+        let first_waker = Waker::from(first.clone());
+        let second_waker = Waker::from(second.clone());
+
+        let mut dequeue = queue.dequeue_async();
+        let mut other = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut Context::from_waker(&first_waker)).is_pending());
+        assert!(Pin::new(&mut dequeue).poll(&mut Context::from_waker(&first_waker)).is_pending());
+        assert!(Pin::new(&mut other).poll(&mut Context::from_waker(&first_waker)).is_pending());
+        assert_eq!(queue.lock_waiters().list.len(), 2);
+
+        //Waker is replaced when task moves
+        assert!(Pin::new(&mut dequeue).poll(&mut Context::from_waker(&second_waker)).is_pending());
+        assert_eq!(queue.lock_waiters().list.len(), 2);
+
+        drop(other);
+        assert_eq!(queue.lock_waiters().list.len(), 1);
+
+        assert!(queue.enqueue(1).is_ok());
+        assert_eq!(first.0.load(Ordering::Acquire), 0);
+        assert_eq!(second.0.load(Ordering::Acquire), 1);
+        assert_eq!(Pin::new(&mut dequeue).poll(&mut Context::from_waker(&second_waker)), Poll::Ready(1));
+        assert!(queue.lock_waiters().list.is_empty());
+
+        let mut dequeue = queue.dequeue_async();
+        assert!(Pin::new(&mut dequeue).poll(&mut Context::from_waker(&first_waker)).is_pending());
+        drop(dequeue);
+        assert!(queue.lock_waiters().list.is_empty());
+        assert!(!queue.has_waiters.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn should_not_leave_blocking_registration() {
+        let queue = Arc::new(Queue::<usize, 4>::new());
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.dequeue_blocking())
+        };
+
+        while queue.lock_waiters().list.is_empty() {
+            thread::yield_now();
+        }
+
+        //Spurious unpark must not duplicate registration
+        consumer.thread().unpark();
+        assert!(queue.enqueue(5).is_ok());
+        assert_eq!(consumer.join().expect("Consumer to finish"), 5);
+        assert!(queue.lock_waiters().list.is_empty());
+
+        assert!(queue.enqueue(6).is_ok());
+        assert_eq!(queue.dequeue_blocking(), 6);
+        assert!(queue.lock_waiters().list.is_empty());
+    }
+
+    #[test]
+    fn should_pass_all_items_between_multiple_producers_and_consumers() {
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+
+        let queue = Arc::new(Queue::<usize, 64>::new());
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let mut seen = vec![false; STRESS_ITEMS];
+
+        let producers = (0..PRODUCERS).map(|producer| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for item in (producer..STRESS_ITEMS).step_by(PRODUCERS) {
+                    let mut item = item;
+                    while let Err(rejected) = queue.enqueue(item) {
+                        item = rejected;
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let consumers = (0..CONSUMERS).map(|_| {
+            let queue = queue.clone();
+            let consumed = consumed.clone();
+            thread::spawn(move || {
+                let mut items = Vec::new();
+                while consumed.load(Ordering::Acquire) < STRESS_ITEMS {
+                    match queue.dequeue() {
+                        Some(item) => {
+                            consumed.fetch_add(1, Ordering::AcqRel);
+                            items.push(item);
+                        },
+                        None => thread::yield_now(),
+                    }
+                }
+                items
+            })
+        }).collect::<Vec<_>>();
+
+        for producer in producers {
+            producer.join().expect("Producer to finish");
+        }
+
+        for consumer in consumers {
+            for item in consumer.join().expect("Consumer to finish") {
+                assert!(!seen[item], "Item {} is dequeued twice", item);
+                seen[item] = true;
+            }
+        }
+
+        assert!(seen.iter().all(|seen| *seen));
+        assert!(queue.dequeue().is_none());
+    }
+}
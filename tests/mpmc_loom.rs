@@ -0,0 +1,145 @@
+//! Model tests for `utils::mpmc`
+//!
+//! Run with `RUSTFLAGS="--cfg mpmc_loom" cargo test --release --test mpmc_loom`
+#![cfg(mpmc_loom)]
+
+#[allow(unused)]
+#[path = "../src/utils/mpmc.rs"]
+mod mpmc;
+
+use mpmc::Queue;
+
+use loom::sync::Arc;
+use loom::thread;
+
+#[test]
+fn concurrent_enqueue_should_keep_all_items() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+
+        let producers = (1..=2).map(|item| {
+            let queue = queue.clone();
+            thread::spawn(move || assert!(queue.enqueue(item).is_ok()))
+        }).collect::<Vec<_>>();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        let mut items = [queue.dequeue().unwrap(), queue.dequeue().unwrap()];
+        items.sort_unstable();
+        assert_eq!(items, [1, 2]);
+        assert!(queue.dequeue().is_none());
+    });
+}
+
+#[test]
+fn concurrent_dequeue_should_see_complete_item() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || assert!(queue.enqueue(1).is_ok()))
+        };
+
+        let consumed = queue.dequeue();
+        producer.join().unwrap();
+
+        match consumed {
+            Some(item) => assert_eq!(item, 1),
+            None => assert_eq!(queue.dequeue(), Some(1)),
+        }
+        assert!(queue.dequeue().is_none());
+    });
+}
+
+#[test]
+fn concurrent_consumers_should_not_duplicate_item() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+        assert!(queue.enqueue(1).is_ok());
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.dequeue())
+        };
+
+        let first = queue.dequeue();
+        let second = consumer.join().unwrap();
+
+        match (first, second) {
+            (Some(1), None) | (None, Some(1)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn concurrent_enqueue_should_respect_capacity() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+        assert!(queue.enqueue(0).is_ok());
+
+        let producers = (1..=2).map(|item| {
+            let queue = queue.clone();
+            thread::spawn(move || queue.enqueue(item).is_ok())
+        }).collect::<Vec<_>>();
+
+        let accepted = producers.into_iter().map(|producer| producer.join().unwrap()).filter(|accepted| *accepted).count();
+        assert_eq!(accepted, 1);
+
+        assert_eq!(queue.dequeue(), Some(0));
+        assert!(queue.dequeue().is_some());
+        assert!(queue.dequeue().is_none());
+    });
+}
+
+#[test]
+fn producer_and_consumer_should_wrap_around() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for item in 0..3 {
+                    while queue.enqueue(item).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        for expected in 0..3 {
+            loop {
+                match queue.dequeue() {
+                    Some(item) => {
+                        assert_eq!(item, expected);
+                        break;
+                    },
+                    None => thread::yield_now(),
+                }
+            }
+        }
+
+        producer.join().unwrap();
+        assert!(queue.dequeue().is_none());
+    });
+}
+
+#[test]
+fn async_consumer_should_be_woken_by_producer() {
+    loom::model(|| {
+        let queue = Arc::new(Queue::<usize, 2>::new());
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || assert!(queue.enqueue(1).is_ok()))
+        };
+
+        assert_eq!(loom::future::block_on(queue.dequeue_async()), 1);
+        producer.join().unwrap();
+        assert!(queue.dequeue().is_none());
+    });
+}